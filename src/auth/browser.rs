@@ -0,0 +1,231 @@
+use std::{sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use reqwest::Url;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use crate::config::Config;
+
+/// Path of the SmartBeans login page that redirects back to the CLI
+pub const BROWSER_LOGIN_PATH: &str = "/login";
+/// How long we wait for the browser to come back
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+const SUCCESS_PAGE: &str = "<html><body><h1>Login successful</h1><p>You can close this window and return to your terminal.</p></body></html>";
+const FAILURE_PAGE: &str = "<html><body><h1>Login failed</h1><p>No session token was received. Please try again.</p></body></html>";
+
+/// A temporary HTTP listener on the loopback interface that SmartBeans redirects to after login.
+///
+/// The token is accepted from a `token` query parameter or a `token` cookie.
+/// A random `state` is passed along and has to come back unchanged,
+/// so other local processes can't slip us a token of their choosing.
+pub struct Callback {
+    listener: TcpListener,
+    state: String,
+}
+
+impl Callback {
+    pub async fn bind() -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+
+        let mut state = [0u8; 16];
+        OsRng.fill_bytes(&mut state);
+
+        Ok(Self {
+            listener,
+            state: URL_SAFE_NO_PAD.encode(state),
+        })
+    }
+
+    pub fn redirect_uri(&self) -> anyhow::Result<String> {
+        Ok(format!(
+            "http://127.0.0.1:{}/callback",
+            self.listener.local_addr()?.port()
+        ))
+    }
+
+    /// The page to open in the browser
+    pub fn login_url(&self, cfg: &Config) -> anyhow::Result<Url> {
+        let mut url = Url::parse(&format!("{}{}", cfg.host, BROWSER_LOGIN_PATH))?;
+        url.query_pairs_mut()
+            .append_pair("redirect_uri", &self.redirect_uri()?)
+            .append_pair("state", &self.state)
+            .append_pair("course", &cfg.course);
+        Ok(url)
+    }
+
+    /// Serves requests until one of them carries a token, or the timeout runs out.
+    /// Anything else is answered and ignored, only our callback ends the login.
+    pub async fn wait_for_token(self) -> anyhow::Result<String> {
+        let state: Arc<str> = self.state.into();
+        let (tokens, mut received) = mpsc::channel(1);
+
+        tokio::time::timeout(CALLBACK_TIMEOUT, async {
+            loop {
+                tokio::select! {
+                    accepted = self.listener.accept() => {
+                        let (stream, _) = accepted?;
+                        // each on its own, a connection that never sends anything mustn't hold up the callback
+                        tokio::spawn(serve(stream, state.clone(), tokens.clone()));
+                    }
+                    Some(token) = received.recv() => return anyhow::Ok(token),
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out waiting for the browser login to complete"))?
+    }
+}
+
+/// Answers a single connection, passing the token on if it was our callback
+async fn serve(mut stream: TcpStream, state: Arc<str>, tokens: mpsc::Sender<String>) {
+    // browsers like to ask for a favicon, open sockets they never use and the like,
+    // and whoever sent a request may have hung up already, so failing to respond is fine
+    match read_token(&mut stream, &state).await {
+        Ok(Some(token)) => {
+            let _ = respond(&mut stream, "200 OK", SUCCESS_PAGE).await;
+            let _ = tokens.send(token).await;
+        }
+        Ok(None) => {
+            let _ = respond(&mut stream, "404 Not Found", FAILURE_PAGE).await;
+        }
+        Err(e) => {
+            eprintln!("Ignoring a request to the login callback: {}", e);
+            let _ = respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await;
+        }
+    }
+}
+
+/// Returns the token if this request is the callback, `None` for unrelated requests
+async fn read_token(stream: &mut TcpStream, state: &str) -> anyhow::Result<Option<String>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    // we only care about the request line and headers
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > 64 * 1024 {
+            anyhow::bail!("The callback request is too large");
+        }
+    }
+
+    // browsers open connections ahead of time and close them unused
+    if buf.is_empty() {
+        return Ok(None);
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let mut lines = head.lines();
+    let target = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or_else(|| anyhow::anyhow!("Malformed callback request"))?;
+
+    let url = Url::parse(&format!("http://127.0.0.1{}", target))?;
+    if url.path() != "/callback" {
+        return Ok(None);
+    }
+
+    let query_param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    if query_param("state").as_deref() != Some(state) {
+        anyhow::bail!("The login callback carried an unexpected state, refusing its token");
+    }
+
+    let cookie_token = lines
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix("token=").map(str::to_string));
+
+    match query_param("token").or(cookie_token) {
+        Some(token) if !token.is_empty() => Ok(Some(token)),
+        _ => anyhow::bail!("The login callback didn't carry a session token"),
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> anyhow::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(stream.shutdown().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the identity provider: follows the redirect the way a browser would after login
+    #[tokio::test]
+    async fn test_callback_captures_token() {
+        let callback = Callback::bind().await.unwrap();
+        let mut cfg = Config::default();
+        cfg.host = "https://smartbeans.example".to_string();
+        cfg.course = "ckurs".to_string();
+
+        let login_url = callback.login_url(&cfg).unwrap();
+        let param = |name: &str| {
+            login_url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .unwrap()
+        };
+        let redirect = format!(
+            "{}?token=abc%2B123&state={}",
+            param("redirect_uri"),
+            param("state")
+        );
+
+        let server = tokio::spawn(callback.wait_for_token());
+
+        let not_found = reqwest::get(redirect.replace("/callback", "/favicon.ico"))
+            .await
+            .unwrap();
+        assert_eq!(not_found.status(), 404);
+
+        let res = reqwest::get(redirect).await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(server.await.unwrap().unwrap(), "abc+123");
+    }
+
+    #[tokio::test]
+    async fn test_callback_ignores_bad_requests() {
+        let callback = Callback::bind().await.unwrap();
+        let redirect_uri = callback.redirect_uri().unwrap();
+        let redirect = format!("{}?token=abc&state={}", redirect_uri, callback.state);
+        let addr = callback.listener.local_addr().unwrap();
+
+        let server = tokio::spawn(callback.wait_for_token());
+
+        // a preconnect that is closed without a request, and one that is kept open but never used
+        drop(TcpStream::connect(addr).await.unwrap());
+        let _idle = TcpStream::connect(addr).await.unwrap();
+
+        let forged = reqwest::get(format!("{}?token=forged&state=forged", redirect_uri))
+            .await
+            .unwrap();
+        assert_eq!(forged.status(), 400);
+
+        let res = reqwest::get(redirect).await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(server.await.unwrap().unwrap(), "abc");
+    }
+}
@@ -0,0 +1,340 @@
+mod browser;
+
+use std::collections::HashMap;
+
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use reqwest::{header::COOKIE, StatusCode};
+use serde::Deserialize;
+
+use crate::{
+    config::{self, Context},
+    credentials,
+    util::{is_interactive, shell_command},
+};
+
+/// Environment variable that can hold a session token for `sbcli login`
+pub const TOKEN_ENV_VAR: &str = "SBCLI_TOKEN";
+/// Environment variable that can hold the password, for scripts and CI
+pub const PASSWORD_ENV_VAR: &str = "SBCLI_PASSWORD";
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    token: String,
+    /// Not every SmartBeans version tells us when the session ends
+    #[serde(default, rename = "expiresAt")]
+    expires_at: Option<DateTime<Utc>>,
+    /// Session length in seconds
+    #[serde(default, rename = "expiresIn")]
+    expires_in: Option<i64>,
+}
+
+impl LoginResponse {
+    fn expiry(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+            .or_else(|| self.expires_in.map(|s| Utc::now() + Duration::seconds(s)))
+    }
+}
+
+/// The claims we care about if the token is a JWT
+#[derive(Debug, Default, Deserialize)]
+pub struct TokenClaims {
+    exp: Option<i64>,
+    sub: Option<String>,
+    username: Option<String>,
+}
+
+impl TokenClaims {
+    /// Decodes the payload of a JWT without verifying it, that's the server's job.
+    /// Returns `None` for opaque tokens.
+    pub fn decode(token: &str) -> Option<Self> {
+        let payload = token.split('.').nth(1)?;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    pub fn expiry(&self) -> Option<DateTime<Utc>> {
+        self.exp.and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.username.as_deref().or(self.sub.as_deref())
+    }
+}
+
+/// Remembers a freshly obtained session.
+/// The expiry reported by the server wins over the one in the token.
+fn store_session(
+    cfg: &mut config::Config,
+    token: String,
+    expiry: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    cfg.token_expiry = expiry.or_else(|| TokenClaims::decode(&token)?.expiry());
+    // a token from the environment is used as is and never stored, see `credentials::load_token`
+    if !(cfg.is_overridden("token") && cfg.token == token) {
+        credentials::store_token(cfg, &token)?;
+    }
+    cfg.last_login_time = Some(Utc::now());
+
+    // the server just accepted the token, so an expiry that already passed is wrong.
+    // Forget it and estimate from the login time, otherwise every command would check the session again.
+    if !cfg.is_token_valid() {
+        cfg.token_expiry = None;
+    }
+
+    config::Config::store(cfg)
+}
+
+/// Makes sure there is a session before talking to the API.
+/// This only catches sessions we know to be missing or stale, the server may still reject the token,
+/// which `ApiClient` handles by logging in again.
+pub async fn ensure_auth(ctx: &Context) -> anyhow::Result<()> {
+    let mut cfg = ctx.config()?;
+    let token = credentials::load_token(&mut cfg)?;
+
+    if token.is_empty() {
+        login(ctx, false).await?;
+    } else if cfg.last_login_time.is_none() || !cfg.is_token_valid() {
+        // tokens generated in the web interface may outlive our local session estimate,
+        // so ask the server before falling back to a password prompt
+        if validate_token(&cfg, &token).await? {
+            let expiry = cfg.token_expiry;
+            store_session(&mut cfg, token, expiry)?;
+        } else {
+            login(ctx, false).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether we can get a password without asking the user
+pub fn has_unattended_password(cfg: &config::Config) -> bool {
+    std::env::var_os(PASSWORD_ENV_VAR).is_some() || cfg.password_command.is_some()
+}
+
+/// Gets the password from the first available source:
+/// stdin (if requested), the environment, the configured `password_command`, and finally a prompt.
+/// Fails instead of prompting if there's no terminal, since that would hang or error obscurely.
+fn read_password(cfg: &config::Config, from_stdin: bool) -> anyhow::Result<String> {
+    if from_stdin {
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        return Ok(input.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    if let Ok(password) = std::env::var(PASSWORD_ENV_VAR) {
+        return Ok(password);
+    }
+
+    if let Some(command) = &cfg.password_command {
+        let output = shell_command(command).output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "The password command `{}` failed with {}",
+                command,
+                output.status
+            );
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Ok(stdout.lines().next().unwrap_or_default().to_string());
+    }
+
+    if !is_interactive() {
+        anyhow::bail!(
+            "You need to login, but there is no terminal to ask for your password.\n\
+             Use `{0} login --password-stdin`, set {1}, configure a password command, \
+             or use `{0} login --token`.",
+            env!("CARGO_PKG_NAME"),
+            PASSWORD_ENV_VAR
+        );
+    }
+
+    println!("You need to login to continue.");
+    println!(
+        "If your account has no password, use `{} login --token` instead.",
+        env!("CARGO_PKG_NAME")
+    );
+    Ok(rpassword::prompt_password("Password: ")?)
+}
+
+/// POST /api/auth/login
+pub async fn login(ctx: &Context, password_stdin: bool) -> anyhow::Result<()> {
+    let mut cfg = ctx.config()?;
+
+    let password = read_password(&cfg, password_stdin)?;
+
+    let client = reqwest::Client::new();
+
+    let mut data = HashMap::new();
+    data.insert("username", &cfg.user);
+    data.insert("password", &password);
+    data.insert("course", &cfg.course);
+
+    let res = client
+        .post(format!("{}/api/auth/login", cfg.host))
+        .json(&data)
+        .send()
+        .await?;
+
+    if res.status().is_success() {
+        println!("Login successful");
+
+        let body: LoginResponse = res.json().await?;
+        let expiry = body.expiry();
+
+        store_session(&mut cfg, body.token, expiry)?;
+    } else {
+        println!("Login response indicates failure: {:?}", res);
+        return Err(anyhow::anyhow!("Login failed"));
+    }
+
+    Ok(())
+}
+
+/// Stores a session token generated in the SmartBeans web interface,
+/// after checking that the server accepts it
+pub async fn login_with_token(ctx: &Context, token: &str) -> anyhow::Result<()> {
+    let mut cfg = ctx.config()?;
+
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("The session token must not be empty");
+    }
+
+    if !validate_token(&cfg, token).await? {
+        anyhow::bail!(
+            "The session token was rejected by {}. Please generate a new one in SmartBeans.",
+            cfg.host
+        );
+    }
+
+    println!("Login successful");
+
+    store_session(&mut cfg, token.to_string(), None)?;
+
+    Ok(())
+}
+
+/// Logs in through the SmartBeans web interface, which covers OAuth/LTI accounts.
+/// We open the login page in the browser and wait for it to redirect to a local listener with the token.
+pub async fn login_with_browser(ctx: &Context) -> anyhow::Result<()> {
+    let cfg = ctx.config()?;
+
+    let callback = browser::Callback::bind().await?;
+    let url = callback.login_url(&cfg)?;
+
+    println!("Opening the SmartBeans login page in your browser.");
+    println!("If it doesn't open, visit this URL:\n{}", url);
+    if open::that(url.as_str()).is_err() {
+        eprintln!("Could not open a browser.");
+    }
+
+    let token = callback.wait_for_token().await?;
+
+    login_with_token(ctx, &token).await
+}
+
+/// GET /api/courses/{courseId}/progress
+/// Any authenticated endpoint will do, progress is cheap and scoped to the configured course
+pub async fn validate_token(cfg: &config::Config, token: &str) -> anyhow::Result<bool> {
+    let url = format!("{}/api/courses/{}/progress", cfg.host, cfg.course);
+
+    let res = reqwest::Client::new()
+        .get(url)
+        .header(COOKIE, format!("token={}", token))
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Ok(false);
+    }
+
+    // an invalid token may still yield a 200 with an error page, so make sure the body is what we expect
+    Ok(res.json::<Vec<usize>>().await.is_ok())
+}
+
+/// POST /api/auth/logout, then forgets the session locally.
+/// The local session is wiped even if the server can't be reached, that's what matters on shared machines.
+pub async fn logout(ctx: &Context) -> anyhow::Result<()> {
+    let mut cfg = ctx.config()?;
+    let token = credentials::load_token(&mut cfg)?;
+
+    if !token.is_empty() {
+        let res = reqwest::Client::new()
+            .post(format!("{}/api/auth/logout", cfg.host))
+            .header(COOKIE, format!("token={}", token))
+            .send()
+            .await;
+
+        match res {
+            Ok(res) if res.status().is_success() => println!("Ended the session on {}", cfg.host),
+            // older SmartBeans versions have no logout endpoint, the token simply runs out
+            Ok(res) if res.status() == StatusCode::NOT_FOUND => {}
+            Ok(res) => eprintln!(
+                "Could not end the session on the server ({}), removing it locally anyway.",
+                res.status()
+            ),
+            Err(e) => eprintln!(
+                "Could not reach {} ({}), removing the session locally anyway.",
+                cfg.host, e
+            ),
+        }
+    }
+
+    credentials::backend(&cfg)?.erase(&mut cfg)?;
+    cfg.last_login_time = None;
+    cfg.token_expiry = None;
+    config::Config::store(&cfg)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_jwt_claims() {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let payload = engine.encode(r#"{"sub":"student","exp":1700000000}"#);
+        let token = format!(
+            "{}.{}.signature",
+            engine.encode(r#"{"alg":"HS256"}"#),
+            payload
+        );
+
+        let claims = TokenClaims::decode(&token).unwrap();
+        assert_eq!(claims.subject(), Some("student"));
+        assert_eq!(claims.expiry().unwrap().timestamp(), 1700000000);
+
+        assert!(TokenClaims::decode("an-opaque-session-id").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_env_token_is_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let stored = dir.path().join("stored");
+
+        let ctx = Context::new(Some(dir.path().join("config.ron")), None, None, false).unwrap();
+        let mut cfg = ctx.config().unwrap();
+        cfg.credential_store = credentials::CredentialStoreKind::Helper;
+        // a credential helper that only records what it's asked to store
+        cfg.credential_helper = Some(format!("sh -c 'cat >> {}' helper", stored.display()));
+        cfg.apply_env_from(|var| (var == TOKEN_ENV_VAR).then(|| "from-env".to_string()))
+            .unwrap();
+
+        store_session(&mut cfg, "from-env".to_string(), None).unwrap();
+        assert!(!stored.exists());
+
+        // a token the server just issued is stored, even while the environment overrides it
+        store_session(&mut cfg, "issued".to_string(), None).unwrap();
+        assert!(std::fs::read_to_string(&stored)
+            .unwrap()
+            .contains("password=issued"));
+    }
+}
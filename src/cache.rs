@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::util::write_atomic;
+
+/// A response as it was received from SmartBeans, with the time it was fetched
+#[derive(Serialize, Deserialize)]
+pub struct Cached<T> {
+    pub fetched_at: DateTime<Utc>,
+    pub data: T,
+}
+
+/// Keeps the last response of each request, so commands can work with it while SmartBeans can't be reached.
+/// There's one cache per profile, its entries are JSON files named after their key.
+#[derive(Clone, Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cached response, `None` if there is none or it can't be read anymore, e.g. after a format change
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Option<Cached<T>> {
+        let contents = std::fs::read_to_string(self.path(key)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn store<T: Serialize>(&self, key: &str, data: &T) -> anyhow::Result<()> {
+        let entry = Cached {
+            fetched_at: Utc::now(),
+            data,
+        };
+        write_atomic(&self.path(key), &serde_json::to_vec(&entry)?)
+    }
+
+    /// Removes all cached responses
+    pub fn clear(&self) -> anyhow::Result<()> {
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_load() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("cache");
+        let cache = ResponseCache::new(dir.clone());
+
+        assert!(cache.load::<Vec<usize>>("progress").is_none());
+
+        cache.store("progress", &vec![1, 3]).unwrap();
+        let cached = cache.load::<Vec<usize>>("progress").unwrap();
+        assert_eq!(cached.data, vec![1, 3]);
+        assert!(Utc::now() - cached.fetched_at < chrono::Duration::minutes(1));
+
+        // entries that don't match the expected type are treated as missing
+        assert!(cache.load::<String>("progress").is_none());
+
+        cache.clear().unwrap();
+        assert!(!dir.exists());
+    }
+}
@@ -1,31 +1,235 @@
 use std::path::Path;
 
-use anyhow::Ok;
+use anyhow::{Context as _, Ok};
+use chrono::{DateTime, Local, Utc};
 use colored::Colorize;
 use itertools::Itertools;
-use serde_json::json;
 
 use crate::{
     auth::{self, ensure_auth},
-    config::{self, Config},
-    requests,
-    tasks::{self, files::sync_tasks_async},
-    util::prompt_for_consent,
+    config::{
+        self,
+        activity::TaskActivity,
+        overrides::{Origin, CONFIG_FIELDS},
+        profile::{self, Profile, DEFAULT_PROFILE},
+        queue::SubmissionQueue,
+        settings::{OutputFormat, SETTINGS},
+        Config, Context,
+    },
+    credentials::{self, CredentialStoreKind},
+    requests::{self, ApiFlavor, HostProbe},
+    tasks::{
+        self,
+        files::sync_tasks_async,
+        graph::{GraphFormat, TaskGraph},
+        models::{SubmissionGet, SubmissionPost, Task},
+    },
+    util::{
+        did_you_mean, format_age, is_interactive, pick, prompt, prompt_for_consent,
+        prompt_for_consent_default_no,
+    },
 };
 
-pub async fn configure(username: &str, course: &str, host: &str) -> anyhow::Result<()> {
-    let mut cfg = Config::load()?;
+/// Makes sure the course exists on the server, or lets the user pick one if none was given.
+/// If the server can't list courses, e.g. because we're not logged in yet, the given course is taken as is.
+async fn select_course(host: &str, token: &str, course: Option<&str>) -> anyhow::Result<String> {
+    let courses = match requests::get_courses(host, token).await {
+        Result::Ok(courses) if !courses.is_empty() => courses,
+        result => {
+            if let Err(e) = result {
+                eprintln!("Could not fetch the list of courses: {}", e);
+            }
+            return match course {
+                Some(course) => Ok(course.to_string()),
+                None => anyhow::bail!("Please pass the course name with `--course`."),
+            };
+        }
+    };
+
+    if let Some(course) = course {
+        if courses.iter().any(|c| c.name() == course) {
+            return Ok(course.to_string());
+        }
+
+        let names = courses.iter().map(|c| c.name());
+        let hint = match did_you_mean(course, names) {
+            Some(suggestion) => format!(" Did you mean `{}`?", suggestion),
+            None => format!(
+                " Available courses: {}",
+                courses.iter().map(|c| c.name()).join(", ")
+            ),
+        };
+        anyhow::bail!("There is no course named `{}` on {}.{}", course, host, hint);
+    }
+
+    if !is_interactive() {
+        anyhow::bail!("Please pass the course name with `--course`.");
+    }
+
+    match pick("Which course do you want to work on?", &courses) {
+        Some(i) => Ok(courses[i].name().to_string()),
+        None => anyhow::bail!("No course selected."),
+    }
+}
+
+/// On a first configure there's no session to list the courses with, so the course is checked once we have one.
+/// Lets the user pick another course if it doesn't exist, or tells them how to fix it if we can't ask.
+async fn check_course(ctx: &Context) -> anyhow::Result<()> {
+    let mut cfg = ctx.config()?;
+    let token = credentials::load_token(&mut cfg)?;
+    if token.is_empty() {
+        return Ok(());
+    }
+
+    match select_course(&cfg.host, &token, Some(&cfg.course)).await {
+        Result::Ok(_) => Ok(()),
+        Err(e) if is_interactive() && !cfg.is_overridden("course") => {
+            eprintln!("{}", e.to_string().bright_red());
+            let course = select_course(&cfg.host, &token, None).await?;
+            // the session stays valid, it just has to be stored for the new course
+            let moved_token = credentials::take_token(&mut cfg)?;
+            cfg.course = course;
+            if let Some(token) = moved_token {
+                credentials::store_token(&mut cfg, &token)?;
+            }
+            cfg.store()
+        }
+        Err(e) => anyhow::bail!(
+            "{} Change it with `{} configure --course <COURSE>`.",
+            e,
+            env!("CARGO_PKG_NAME")
+        ),
+    }
+}
+
+/// Normalizes the host and makes sure it's a SmartBeans API.
+/// Only a host that answers with something else is an error, an unreachable one might just be offline for now.
+async fn check_host(host: &str) -> anyhow::Result<String> {
+    let host = requests::normalize_host(host)?;
+
+    match requests::probe_host(&host).await {
+        Result::Ok(HostProbe::SmartBeans) => {
+            println!("Found a SmartBeans API at {}", host.bright_blue());
+        }
+        Result::Ok(HostProbe::Other(reason)) => {
+            anyhow::bail!(
+                "{} doesn't look like a SmartBeans instance: {}",
+                host,
+                reason
+            )
+        }
+        Err(e) => eprintln!("Could not reach {} to check it: {}", host, e),
+    }
 
-    cfg.version = env!("CARGO_PKG_VERSION").to_string();
-    cfg.course = course.to_string();
-    cfg.user = username.to_string();
-    cfg.host = host.to_string();
+    Ok(host)
+}
+
+/// Asks for the host until we get one that checks out, the current one is the default
+async fn prompt_host(current: &str) -> anyhow::Result<String> {
+    if !is_interactive() {
+        anyhow::bail!("Please pass the SmartBeans host with `--host`.");
+    }
+
+    loop {
+        let input = prompt(
+            "SmartBeans host, e.g. https://c109-223.cloud.gwdg.de",
+            Some(current),
+        )?;
+        // there's no current host to fall back to
+        if input.is_empty() {
+            continue;
+        }
+
+        match check_host(&input).await {
+            Result::Ok(checked) => return Ok(checked),
+            Err(e) => eprintln!("{}", e.to_string().bright_red()),
+        }
+    }
+}
+
+/// Sets up the current profile.
+/// Anything not passed on the command line is asked for interactively, so running it without arguments starts a wizard.
+#[allow(clippy::too_many_arguments)]
+pub async fn configure(
+    ctx: &Context,
+    username: Option<&str>,
+    course: Option<&str>,
+    host: Option<&str>,
+    api_flavor: Option<ApiFlavor>,
+    credential_store: Option<CredentialStoreKind>,
+    credential_helper: Option<&str>,
+    password_command: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut cfg = ctx.config()?;
+
+    let wizard = username.is_none() || host.is_none();
+    if wizard && !is_interactive() {
+        anyhow::bail!("Please pass `--username` and `--host`, or run `configure` in a terminal to be guided through the setup.");
+    }
+
+    let host = match host {
+        Some(host) => check_host(host).await?,
+        None => prompt_host(&cfg.host).await?,
+    };
+    let username = match username {
+        Some(username) => username.to_string(),
+        None => prompt("Username", Some(&cfg.user))?,
+    };
+
+    // a session for the same host lets us list the courses that account can see
+    let token = if cfg.host == host {
+        credentials::load_token(&mut cfg)?
+    } else {
+        String::new()
+    };
+    let course = select_course(&host, &token, course).await?;
+    let mut course_checked = !token.is_empty();
+
+    // move the token while `cfg` still describes the account it belongs to
+    let helper = credential_helper.map(str::to_string);
+    let store = credential_store.or(helper.as_ref().map(|_| CredentialStoreKind::Helper));
+    if let Some(store) = store {
+        credentials::switch_backend(&mut cfg, store, helper)?;
+    }
+
+    // stored tokens are keyed by the account, so they have to move along when it changes
+    let account_changed = cfg.stored_value("host") != host
+        || cfg.stored_value("user") != username
+        || cfg.stored_value("course") != course;
+    let moved_token = if account_changed {
+        credentials::take_token(&mut cfg)?
+    } else {
+        None
+    };
+
+    // otherwise it's detected from the first submissions we fetch, see `ApiClient`
+    if api_flavor.is_some() || cfg.host != host {
+        cfg.api_flavor = api_flavor;
+    }
+    cfg.course = course;
+    cfg.user = username;
+    cfg.host = host;
+    if let Some(password_command) = password_command {
+        cfg.password_command = Some(password_command.to_string()).filter(|c| !c.is_empty());
+    }
+    if let Some(token) = moved_token {
+        credentials::store_token(&mut cfg, &token)?;
+    }
 
     Config::store(&cfg)?;
 
+    if wizard && prompt_for_consent("Do you want to log in now?") {
+        ensure_auth(ctx).await?;
+        check_course(ctx).await?;
+        course_checked = true;
+    }
+
     if prompt_for_consent("Do you want to sync the exercises now?") {
-        ensure_auth().await?;
-        sync(false, true).await?;
+        ensure_auth(ctx).await?;
+        if !course_checked {
+            check_course(ctx).await?;
+        }
+        sync(ctx, false, true).await?;
 
         println!("{}", "Setup complete!".green());
     } else {
@@ -37,19 +241,397 @@ pub async fn configure(username: &str, course: &str, host: &str) -> anyhow::Resu
     Ok(())
 }
 
-pub async fn login() -> anyhow::Result<()> {
-    ensure_configured()?;
+pub async fn login(
+    ctx: &Context,
+    token: Option<&str>,
+    token_stdin: bool,
+    password_stdin: bool,
+    browser: bool,
+) -> anyhow::Result<()> {
+    ensure_configured(ctx)?;
+
+    if browser {
+        auth::login_with_browser(ctx).await?;
+        return check_course(ctx).await;
+    }
+
+    let token = if token_stdin {
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Some(input)
+    } else if password_stdin {
+        None
+    } else {
+        token
+            .map(str::to_string)
+            .or_else(|| std::env::var(auth::TOKEN_ENV_VAR).ok())
+    };
+
+    match token {
+        Some(token) => auth::login_with_token(ctx, &token).await?,
+        None => auth::login(ctx, password_stdin).await?,
+    }
+
+    if ctx.config()?.is_overridden("token") {
+        println!(
+            "{}",
+            format!(
+                "Note: {} is set, so commands use its token instead of the stored session until you unset it.",
+                auth::TOKEN_ENV_VAR
+            )
+            .yellow()
+        );
+    }
+
+    // `configure` couldn't check the course without a session
+    check_course(ctx).await
+}
+
+pub async fn login_status(ctx: &Context) -> anyhow::Result<()> {
+    ensure_configured(ctx)?;
+    let mut cfg = ctx.config()?;
+    let token = credentials::load_token(&mut cfg)?;
+
+    if token.is_empty() {
+        println!("{}", "You are not logged in.".bright_red());
+        return Ok(());
+    }
+
+    let claims = auth::TokenClaims::decode(&token).unwrap_or_default();
+    let user = match claims.subject() {
+        Some(subject) if subject != cfg.user => format!("{} ({})", cfg.user, subject),
+        _ => cfg.user.clone(),
+    };
+
+    println!("Logged in as {}", user.bright_blue());
+    println!("Course: {}", cfg.course.bright_blue());
+    println!("Host:   {}", cfg.host.bright_blue());
+    let flavor = match cfg.api_flavor {
+        Some(flavor) => flavor.to_string(),
+        None => format!("{} (guessed)", ApiFlavor::guess(&cfg.host)),
+    };
+    println!("API:    {}", flavor);
+
+    let expiry = match cfg.session_expiry() {
+        Some(expiry) => {
+            let remaining = expiry.signed_duration_since(chrono::Utc::now());
+            let estimate = if cfg.token_expiry.is_none() {
+                " (estimated)"
+            } else {
+                ""
+            };
+
+            if remaining.num_seconds() <= 0 {
+                format!("{}{}, expired", expiry.to_rfc2822(), estimate)
+                    .bright_red()
+                    .to_string()
+            } else {
+                format!(
+                    "{}{}, in {}h {}m",
+                    expiry.to_rfc2822(),
+                    estimate,
+                    remaining.num_hours(),
+                    remaining.num_minutes() % 60
+                )
+            }
+        }
+        None => "unknown".to_string(),
+    };
+    println!("Session expires: {}", expiry);
+
+    match auth::validate_token(&cfg, &token).await {
+        Result::Ok(true) => println!("{}", "The server accepts your session.".green()),
+        Result::Ok(false) => println!(
+            "{}",
+            "The server rejected your session, please log in again.".bright_red()
+        ),
+        Err(e) => println!("Could not reach {} to check the session: {}", cfg.host, e),
+    }
+
+    Ok(())
+}
+
+pub async fn logout(ctx: &Context, all: bool) -> anyhow::Result<()> {
+    auth::logout(ctx).await?;
+
+    if all {
+        // submissions are synced copies of server data, the user's own code stays untouched
+        let meta = config::meta::Meta::load(ctx)?;
+        for task in meta.tasks() {
+            if let Some(workspace) = meta.get_task_path(task.taskid) {
+                let submissions_dir = workspace.join("submissions");
+                if submissions_dir.exists() {
+                    std::fs::remove_dir_all(submissions_dir)?;
+                }
+            }
+        }
+        config::meta::Meta::remove(ctx, &ctx.profile()?)?;
+        ctx.cache(&ctx.profile()?).clear()?;
+        print_dropped_queue(SubmissionQueue::delete(ctx, &ctx.profile()?)?);
+
+        println!("Removed cached task data and submissions.");
+    }
+
+    println!("{}", "Logged out.".green());
+
+    Ok(())
+}
+
+/// Queued submissions are removed with their profile, so they never end up on another host or course
+fn print_dropped_queue(dropped: usize) {
+    if dropped > 0 {
+        println!(
+            "{}",
+            format!(
+                "Dropped {} queued submissions that were never sent.",
+                dropped
+            )
+            .yellow()
+        );
+    }
+}
+
+pub fn profile_list(ctx: &Context) -> anyhow::Result<()> {
+    let file = ctx.load_file()?;
+    let current = ctx.profile()?;
+
+    if file.profiles.is_empty() {
+        println!(
+            "There are no profiles yet. Run `{} configure` to create one.",
+            env!("CARGO_PKG_NAME")
+        );
+        return Ok(());
+    }
+
+    for (name, profile) in &file.profiles {
+        let marker = if *name == current { "*" } else { " " };
+        let details = if profile.is_configured() {
+            format!("{}@{} ({})", profile.user, profile.host, profile.course)
+        } else {
+            "not configured".to_string()
+        };
+
+        println!("{} {} {}", marker, name.bright_blue(), details);
+    }
+
+    Ok(())
+}
+
+pub async fn profile_add(
+    ctx: &Context,
+    name: &str,
+    username: &str,
+    course: Option<&str>,
+    host: &str,
+) -> anyhow::Result<()> {
+    profile::check_name(name)?;
+    if ctx.load_file()?.profiles.contains_key(name) {
+        anyhow::bail!("The profile `{}` already exists", name);
+    }
+
+    let host = check_host(host).await?;
+    let course = select_course(&host, "", course).await?;
+
+    let mut file = ctx.lock_file()?;
+    file.profiles.insert(
+        name.to_string(),
+        Profile {
+            host,
+            user: username.to_string(),
+            course,
+            ..Default::default()
+        },
+    );
+    file.store()?;
+
+    let command_str =
+        format!("`{} profile use {}`", env!("CARGO_PKG_NAME"), name).on_bright_black();
+    println!(
+        "Added profile {}. Run {} to switch to it.",
+        name.bright_blue(),
+        command_str
+    );
+
+    Ok(())
+}
+
+pub fn profile_use(ctx: &Context, name: &str) -> anyhow::Result<()> {
+    profile::check_name(name)?;
+    let mut file = ctx.lock_file()?;
+
+    if !file.profiles.contains_key(name) {
+        anyhow::bail!("There is no profile named `{}`", name);
+    }
+
+    file.active_profile = name.to_string();
+    file.store()?;
+
+    println!("Switched to profile {}", name.bright_blue());
 
-    auth::login().await
+    Ok(())
 }
 
-pub async fn sync(force: bool, submissions: bool) -> anyhow::Result<()> {
-    ensure_configured_and_auth().await?;
-    let api_client = requests::ApiClient::new()?;
+/// Removes a profile with its session and meta. Synced tasks stay on disk, they may contain the user's work.
+pub fn profile_remove(ctx: &Context, name: &str) -> anyhow::Result<()> {
+    // its name decides which files and directories are removed
+    profile::check_name(name)?;
+    if !ctx.load_file()?.profiles.contains_key(name) {
+        anyhow::bail!("There is no profile named `{}`", name);
+    }
+
+    let mut cfg = ctx.config_for(name)?;
+    credentials::backend(&cfg)?.erase(&mut cfg)?;
+    config::meta::Meta::remove(ctx, name)?;
+    ctx.cache(name).clear()?;
+    print_dropped_queue(SubmissionQueue::delete(ctx, name)?);
+
+    let mut file = ctx.lock_file()?;
+    file.profiles.remove(name);
+    if file.active_profile == name {
+        file.active_profile = file
+            .profiles
+            .keys()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+    }
+    file.store()?;
+
+    println!("Removed profile {}.", name.bright_blue());
+    let workspace = ctx.workspace_root(name)?;
+    if workspace.exists() {
+        println!("Its tasks are still in {}", workspace.display());
+    }
+
+    Ok(())
+}
+
+pub fn config_get(ctx: &Context, key: &str) -> anyhow::Result<()> {
+    if let Some(value) = ctx.config()?.get(key)? {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+pub fn config_set(ctx: &Context, key: &str, value: &str) -> anyhow::Result<()> {
+    ensure_setting(key)?;
+
+    let mut file = ctx.lock_file()?;
+    file.settings.set(key, value)?;
+    file.store()?;
+    warn_if_overridden(ctx, key)?;
+
+    if key == "workspace_root" {
+        ask_again_for_workspace_location(ctx)?;
+        ensure_workspace_location(ctx)?;
+    }
+
+    Ok(())
+}
+
+pub fn config_unset(ctx: &Context, key: &str) -> anyhow::Result<()> {
+    ensure_setting(key)?;
+
+    let mut file = ctx.lock_file()?;
+    file.settings.unset(key)?;
+    file.store()?;
+    warn_if_overridden(ctx, key)?;
+
+    if key == "workspace_root" {
+        ask_again_for_workspace_location(ctx)?;
+        ensure_workspace_location(ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Profile fields are changed with `configure`, which validates them against the server
+fn ensure_setting(key: &str) -> anyhow::Result<()> {
+    if CONFIG_FIELDS.iter().any(|field| field.key == key) {
+        anyhow::bail!(
+            "`{}` belongs to the profile, change it with `{} configure`",
+            key,
+            env!("CARGO_PKG_NAME")
+        );
+    }
+    Ok(())
+}
+
+fn warn_if_overridden(ctx: &Context, key: &str) -> anyhow::Result<()> {
+    let origin = ctx.config()?.origin(key)?;
+    if matches!(origin, Origin::Env(_) | Origin::Flag(_)) {
+        println!(
+            "{}",
+            format!("Note: the {} takes precedence over this setting.", origin).yellow()
+        );
+    }
+    Ok(())
+}
+
+pub fn config_list(ctx: &Context, show_origin: bool) -> anyhow::Result<()> {
+    let cfg = ctx.config()?;
+    let entries = CONFIG_FIELDS
+        .iter()
+        .map(|field| (field.key, field.describe()))
+        .chain(
+            SETTINGS
+                .iter()
+                .map(|spec| (spec.key, format!("{} ({})", spec.description, spec.values))),
+        );
+
+    if cfg.settings.output_format == OutputFormat::Json {
+        let mut map = serde_json::Map::new();
+        for (key, _) in entries {
+            let value: serde_json::Value = cfg.get(key)?.into();
+            let value = if show_origin {
+                serde_json::json!({ "value": value, "origin": cfg.origin(key)?.to_string() })
+            } else {
+                value
+            };
+            map.insert(key.to_string(), value);
+        }
+        println!("{}", serde_json::to_string_pretty(&map)?);
+        return Ok(());
+    }
+
+    for (key, description) in entries {
+        let value = cfg.get(key)?.unwrap_or_else(|| "(unset)".to_string());
+        if show_origin {
+            let origin = format!("({})", cfg.origin(key)?);
+            println!(
+                "{} = {} {}",
+                key.bright_blue(),
+                value,
+                origin.bright_black()
+            );
+        } else {
+            println!("{} = {}", key.bright_blue(), value);
+        }
+        println!("    {}", description);
+    }
+
+    Ok(())
+}
+
+pub async fn sync(ctx: &Context, force: bool, submissions: bool) -> anyhow::Result<()> {
+    ensure_online(ctx, "Syncing")?;
+    ensure_configured_and_auth(ctx).await?;
+    let api_client = requests::ApiClient::new(ctx)?;
+    flush_queue_quietly(ctx, &api_client).await;
+    ensure_workspace_location(ctx)?;
 
     // sync_exercises(force, submissions)?;
-    sync_tasks_async(force, submissions, &api_client).await?;
-    let meta = config::meta::Meta::load()?;
+    let summary = sync_tasks_async(ctx, force, submissions, &api_client).await?;
+    let meta = config::meta::Meta::load(ctx)?;
+    print_sync_summary(&meta, &summary);
+
+    if !summary.failed.is_empty() {
+        anyhow::bail!(
+            "Could not sync {} of the tasks, run `{} sync` again to retry.",
+            summary.failed.len(),
+            env!("CARGO_PKG_NAME")
+        );
+    }
 
     let command_str = format!("{} start", env!("CARGO_PKG_NAME")).on_bright_black();
     println!(
@@ -61,22 +643,120 @@ pub async fn sync(force: bool, submissions: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn submit_task(path: &Path) -> anyhow::Result<()> {
-    ensure_fully_setup().await?;
+fn print_sync_summary(meta: &config::meta::Meta, summary: &config::meta::SyncSummary) {
+    let name = |task_id: &usize| match meta.task(*task_id) {
+        Some(task) => format!("{} ({})", task.task_description.shortname, task_id),
+        None => task_id.to_string(),
+    };
+
+    for (task_id, error) in &summary.failed {
+        eprintln!("{} {}: {}", "Failed:".bright_red(), name(task_id), error);
+    }
+
+    if summary.first_sync {
+        return;
+    }
+    if summary.is_empty() {
+        println!("No changes to the tasks since the last sync.");
+        return;
+    }
+
+    for task_id in &summary.added {
+        println!("{} {}", "New:".bright_green(), name(task_id));
+    }
+    for task_id in &summary.updated {
+        println!("{} {}", "Updated:".bright_blue(), name(task_id));
+    }
+    for (task_id, from, to) in &summary.moved {
+        println!(
+            "{} {} from {} to {}",
+            "Moved:".bright_blue(),
+            name(task_id),
+            from.display(),
+            to.display()
+        );
+    }
+    for (task_id, from, to) in &summary.not_moved {
+        println!(
+            "{} {} stays in {}, {} already exists",
+            "Not moved:".yellow(),
+            name(task_id),
+            from.display(),
+            to.display()
+        );
+    }
+    for task_id in &summary.archived {
+        let location = meta
+            .get_task_path(*task_id)
+            .map(|path| format!(", your code is kept in {}", path.display()))
+            .unwrap_or_default();
+        println!(
+            "{} {} was removed from the course{}",
+            "Archived:".yellow(),
+            name(task_id),
+            location
+        );
+    }
+}
+
+pub async fn submit_task(ctx: &Context, path: &Path, task: Option<&str>) -> anyhow::Result<()> {
+    let client = ensure_fully_setup(ctx).await?;
+    let meta = config::meta::Meta::load(ctx)?;
+    let task = match task {
+        Some(query) => meta.find_task(query)?,
+        None => {
+            let task_id = meta.find_task_id(&ctx.config()?, path)?;
+            meta.task(task_id).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Task {} is not among your synced tasks, please run `{} sync`.",
+                    task_id,
+                    env!("CARGO_PKG_NAME")
+                )
+            })?
+        }
+    };
+    // with `--task` nothing else looked at the file yet
+    if !path.exists() {
+        anyhow::bail!("Can't find {}", path.display());
+    }
+    check_language(task, path)?;
+
+    // a snapshot, so the queued submission is what the user submitted even if they keep editing
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let submitted_at = Utc::now();
+    // known to be unreachable already, so don't wait for another connection attempt to time out
+    let res = if client.is_offline() {
+        None
+    } else {
+        match client.submit_task(task.taskid, &content).await {
+            Result::Ok(res) => Some(res),
+            Err(e) if requests::is_unreachable(&e) => None,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let Some(res) = res else {
+        let mut queue = SubmissionQueue::lock(ctx)?;
+        let id = queue.push(task.taskid, path, content, submitted_at);
+        queue.store()?;
 
-    let client = requests::ApiClient::new()?;
-    let res = client.submit_task(path).await?;
+        println!(
+            "SmartBeans can't be reached, so your submission was queued as #{}. It's sent with the next command that reaches SmartBeans, or with `{} queue flush`.",
+            id,
+            env!("CARGO_PKG_NAME")
+        );
+        return Ok(());
+    };
+    let successful = res.result.was_successful();
+    record_submission(ctx, task.taskid, &res.result, submitted_at)?;
 
     // check if the task was solved correctly
-    if res.result.was_successful() {
+    if successful {
         println!(
             "{}",
             "Congratulations! You solved the task correctly!".green()
         );
-
-        let mut meta = config::meta::Meta::load()?;
-        meta.add_solved_task_id(res.result.taskid);
-        meta.save()?;
     } else {
         println!("Unfortunately, your solution is not correct yet. Keep trying!\n");
         // :')
@@ -88,58 +768,19 @@ pub async fn submit_task(path: &Path) -> anyhow::Result<()> {
         }
 
         let submissions = client.get_detailed_submissions(res.result.taskid).await?;
-        // find latest submission
-        // Object {
-        //     "content": String(""),
-        //     "course": String(""),
-        //     "details": Object {},
-        //     "id": Number(0),
-        //     "resultType": String("WRONG_ANSWER|COMPILER_ERROR|SUCCESS"),
-        //     "score": Number(0),
-        //     "simplified": Object {
-        //         "compiler": Object {
-        //             "exitCode": Number(0),
-        //             "stdout": String(""),
-        //         },
-        //         "testCase": Object {
-        //             "exitCode": Number(0),
-        //             "expectedStdout": String("n"),
-        //             "stdin": String(""),
-        //             "stdout": String(""),
-        //         },
-        //     },
-        //     "taskid": Number(0),
-        //     "timestamp": String(""),
-        // }
-        if let Some(latest_submission) = submissions.iter().max_by_key(|s| {
-            let timestamp = s.get("timestamp").unwrap();
-
-            if timestamp.is_u64() {
-                timestamp.as_u64().unwrap()
-            } else {
-                timestamp.as_str().unwrap().parse::<u64>().unwrap()
+        // find latest submission, skipping any we can't make sense of
+        let latest_submission = submissions
+            .into_iter()
+            .filter_map(|s| serde_json::from_value::<SubmissionGet>(s).ok())
+            .max_by_key(|s| s.timestamp);
+
+        if let Some(test_case) = latest_submission.and_then(|s| s.simplified.test_case) {
+            println!("Tests:\n{}\n", test_case.message.as_deref().unwrap_or("()"));
+            if let Some(expected_stdout) = test_case.expected_stdout {
+                println!("Expected stdout:\n{}", expected_stdout);
             }
-        }) {
-            if let Some(object) = latest_submission.get("simplified") {
-                if let Some(object) = object.get("testCase") {
-                    println!(
-                        "Tests:\n{}\n",
-                        object
-                            .get("message")
-                            .unwrap_or(&json!("()"))
-                            .as_str()
-                            .unwrap()
-                    );
-                    if let Some(expected_stdout) = object.get("expectedStdout") {
-                        println!(
-                            "Expected stdout:\n{}",
-                            expected_stdout.as_str().unwrap_or("")
-                        );
-                    }
-                    if let Some(stdout) = object.get("stdout") {
-                        println!("Actual stdout:\n{}", stdout.as_str().unwrap_or(""));
-                    }
-                }
+            if let Some(stdout) = test_case.stdout {
+                println!("Actual stdout:\n{}", stdout);
             }
         }
 
@@ -153,71 +794,481 @@ pub async fn submit_task(path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn list_tasks() -> anyhow::Result<()> {
-    ensure_fully_setup().await?;
+/// Records the result of a submission in the meta: the activity on the task and whether it's solved now
+fn record_submission(
+    ctx: &Context,
+    task_id: usize,
+    result: &SubmissionPost,
+    submitted_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let mut meta = config::meta::Meta::lock(ctx)?;
+    meta.activity_mut(task_id)
+        .record_submission(result.was_successful(), submitted_at);
+    if result.was_successful() {
+        meta.add_solved_task_id(result.taskid);
+    }
+    meta.store()?;
+    Ok(())
+}
+
+/// Sends the queued submissions in the order they were made and reports the result of each.
+/// Stops once SmartBeans can't be reached anymore, the remaining submissions stay queued.
+/// If it can't be reached at all, nothing is printed and the error is returned.
+async fn flush_queue(ctx: &Context, client: &requests::ApiClient) -> anyhow::Result<()> {
+    if client.is_offline() || SubmissionQueue::load(ctx)?.is_empty() {
+        return Ok(());
+    }
 
-    let meta = config::meta::Meta::load().unwrap();
+    // held while sending, so two commands never send the same submission twice
+    let mut queue = SubmissionQueue::lock(ctx)?;
+    let meta = config::meta::Meta::load(ctx)?;
+    let items = queue.items().to_vec();
+    // only once the first one went through, so an unreachable SmartBeans doesn't announce anything
+    let mut header = Some(format!("Sending {} queued submissions:", items.len()));
+
+    for item in items {
+        let name = match meta.task(item.task_id) {
+            Some(task) => task.task_description.shortname.clone(),
+            None => format!("task {}", item.task_id),
+        };
+        let label = format!(
+            "#{} {}, queued {}",
+            item.id,
+            name,
+            format_age(item.queued_at)
+        );
+
+        let res = match client.submit_task(item.task_id, &item.content).await {
+            Err(e) if requests::is_unreachable(&e) && header.is_some() => {
+                client.set_offline();
+                return Err(e);
+            }
+            res => res,
+        };
+        if let Some(header) = header.take() {
+            println!("{}", header);
+        }
+
+        match res {
+            Result::Ok(res) => {
+                queue.remove(item.id);
+                if let Err(e) = record_submission(ctx, item.task_id, &res.result, item.queued_at) {
+                    eprintln!("Could not record the result of {}: {}", label, e);
+                }
+
+                if res.result.was_successful() {
+                    println!("  {}: {}", label, "Solved".green());
+                } else {
+                    println!("  {}: {}", label, res.result.result_type.bright_red());
+                }
+            }
+            Err(e) if requests::is_unreachable(&e) => {
+                client.set_offline();
+                println!(
+                    "SmartBeans can't be reached anymore, {} submissions stay queued.",
+                    queue.items().len()
+                );
+                break;
+            }
+            Err(e) => println!(
+                "  {}: {} It stays queued, drop it with `{} queue drop {}`.",
+                label,
+                format!("{}.", e).bright_red(),
+                env!("CARGO_PKG_NAME"),
+                item.id
+            ),
+        }
+    }
+
+    queue.store()?;
+    Ok(())
+}
+
+/// Sends queued submissions before an online command, without failing the command if that doesn't work.
+/// If SmartBeans can't be reached, they simply stay queued, the command reports that it's offline where it matters.
+async fn flush_queue_quietly(ctx: &Context, client: &requests::ApiClient) {
+    match flush_queue(ctx, client).await {
+        Err(e) if requests::is_unreachable(&e) => {}
+        Err(e) => eprintln!("Could not send the queued submissions: {}", e),
+        Result::Ok(()) => {}
+    }
+}
+
+pub fn queue_list(ctx: &Context) -> anyhow::Result<()> {
+    let queue = SubmissionQueue::load(ctx)?;
+    let meta = config::meta::Meta::load(ctx)?;
+
+    if ctx.config()?.settings.output_format == OutputFormat::Json {
+        let list = queue
+            .items()
+            .iter()
+            .map(|item| {
+                serde_json::json!({
+                    "id": item.id,
+                    "taskid": item.task_id,
+                    "path": item.path,
+                    "queuedAt": item.queued_at,
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
+
+    if queue.is_empty() {
+        println!("No submissions are queued.");
+        return Ok(());
+    }
+
+    for item in queue.items() {
+        let name = meta
+            .task(item.task_id)
+            .map(|task| task.task_description.shortname.clone())
+            .unwrap_or_else(|| format!("task {}", item.task_id));
+        println!(
+            "#{} | Task: {}, File: {}, Queued {}",
+            item.id.to_string().bright_blue(),
+            name.bright_blue(),
+            item.path.display(),
+            format_age(item.queued_at)
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn queue_flush(ctx: &Context) -> anyhow::Result<()> {
+    ensure_online(ctx, "Sending queued submissions")?;
+    ensure_configured_and_auth(ctx).await?;
+
+    if SubmissionQueue::load(ctx)?.is_empty() {
+        println!("No submissions are queued.");
+        return Ok(());
+    }
+
+    let client = requests::ApiClient::new(ctx)?;
+    match flush_queue(ctx, &client).await {
+        Err(e) if requests::is_unreachable(&e) => anyhow::bail!(
+            "SmartBeans can't be reached, the submissions stay queued. ({})",
+            e
+        ),
+        result => result,
+    }
+}
+
+/// Removes one queued submission, or all of them
+pub fn queue_drop(ctx: &Context, id: Option<usize>, all: bool) -> anyhow::Result<()> {
+    let mut queue = SubmissionQueue::lock(ctx)?;
+
+    match id {
+        _ if all => {
+            let dropped = queue.clear();
+            println!("Dropped {} queued submissions.", dropped);
+        }
+        Some(id) => {
+            if queue.remove(id).is_none() {
+                anyhow::bail!(
+                    "There is no queued submission #{}. Run `{} queue list` to see them.",
+                    id,
+                    env!("CARGO_PKG_NAME")
+                );
+            }
+            println!("Dropped the queued submission #{}.", id);
+        }
+        None => anyhow::bail!("Pass the ID of a queued submission, or `--all`."),
+    }
+
+    queue.store()?;
+    Ok(())
+}
+
+/// SmartBeans would only report a compiler error for a file in the wrong language
+fn check_language(task: &Task, path: &Path) -> anyhow::Result<()> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if !extension.eq_ignore_ascii_case(&task.lang) {
+        anyhow::bail!(
+            "{} doesn't look like a solution for `{}`, which expects a `.{}` file.",
+            path.display(),
+            task.task_description.shortname,
+            task.lang
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn list_tasks(ctx: &Context) -> anyhow::Result<()> {
+    let client = ensure_fully_setup(ctx).await?;
+
+    let meta = config::meta::Meta::load(ctx).unwrap();
+    // the progress in the meta is only as fresh as the last sync or submission, that matters once we can't refresh it
+    if client.is_offline() {
+        print_progress_age(ctx)?;
+    }
     let solved = meta.solved_task_ids();
     let tasks = meta.tasks();
+    let active = tasks
+        .iter()
+        .filter(|task| !meta.is_archived(task.taskid))
+        .count();
 
-    for task in tasks.iter().sorted_by(|a, b| a.order_by.cmp(&b.order_by)) {
-        let status = if solved.contains(&task.taskid) {
-            "Completed".green()
-        } else {
-            "Not yet completed".red()
-        };
+    if ctx.config()?.settings.output_format == OutputFormat::Json {
+        let list = tasks
+            .iter()
+            .sorted_by(|a, b| a.order_by.cmp(&b.order_by))
+            .map(|task| {
+                serde_json::json!({
+                    "taskid": task.taskid,
+                    "shortname": task.task_description.shortname,
+                    "title": task.task_description.title,
+                    "orderBy": task.order_by,
+                    "solved": solved.contains(&task.taskid),
+                    "archived": meta.is_archived(task.taskid),
+                    "waitingOn": meta
+                        .missing_prerequisites(task.taskid)
+                        .iter()
+                        .map(|prerequisite| prerequisite.taskid)
+                        .collect::<Vec<_>>(),
+                    "activity": activity_json(&meta.activity(task.taskid).cloned().unwrap_or_default()),
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
 
+    for task in tasks.iter().sorted_by(|a, b| a.order_by.cmp(&b.order_by)) {
         let task_id_str = format!("{:5}", task.taskid).bright_blue(); // Pad task id with spaces
+        let activity = match meta.activity(task.taskid) {
+            Some(activity) if activity.submissions > 0 => format!(
+                " | {} submissions, ~{} spent",
+                activity.submissions,
+                format_duration(activity.active_time())
+            ),
+            _ => String::new(),
+        };
 
         println!(
-            "Task ID {} | Task: {}, Status: {}",
+            "Task ID {} | Task: {}, Status: {}{}",
             task_id_str,
             task.task_description.shortname.bright_blue(),
-            status
+            task_status(&meta, task),
+            activity
         );
     }
 
-    // total solved
-    let ratio = solved.len() as f32 / tasks.len() as f32;
+    // total solved, archived tasks no longer count
+    let solved_active = solved
+        .iter()
+        .filter(|task_id| !meta.is_archived(**task_id))
+        .count();
+    let ratio = solved_active as f32 / active as f32;
 
     println!(
         "You have solved {} out of {} tasks ({}%)",
-        solved.len().to_string().bright_green(),
-        tasks.len(),
+        solved_active.to_string().bright_green(),
+        active,
         (ratio * 100.0).round().to_string().bright_blue()
     );
 
     Ok(())
 }
 
-pub async fn start_task(task_id: Option<usize>) -> anyhow::Result<()> {
-    ensure_fully_setup().await?;
-    let meta = config::meta::Meta::load()?;
+/// The progress in the meta is what was fetched with the last sync, plus what was solved with sbcli since
+fn print_progress_age(ctx: &Context) -> anyhow::Result<()> {
+    let fetched = ctx
+        .cache(&ctx.profile()?)
+        .load::<Vec<usize>>(requests::PROGRESS_CACHE_KEY)
+        .map(|cached| format!(", fetched {}", format_age(cached.fetched_at)))
+        .unwrap_or_default();
+    eprintln!(
+        "{} showing your progress as of the last sync{}",
+        "Offline:".yellow(),
+        fetched
+    );
+    Ok(())
+}
 
-    let task_id = task_id.unwrap_or(meta.next_task_id);
+/// Shows a task with its prerequisites and what you did on it so far
+pub fn info(ctx: &Context, query: &str) -> anyhow::Result<()> {
+    ensure_configured(ctx)?;
+    ensure_tasks_init(ctx)?;
 
-    if let Some(task_path) = meta.get_task_path(task_id) {
-        let mut input = String::new();
-        println!("Do you want to open the task in your default editor? [Y/n]");
-        std::io::stdin().read_line(&mut input)?;
+    let meta = config::meta::Meta::load(ctx)?;
+    let task = meta.find_task(query)?;
+    let activity = meta.activity(task.taskid).cloned().unwrap_or_default();
+    let prerequisites = meta.prerequisites(task.taskid);
+    let directory = meta.get_task_path(task.taskid);
 
-        if input.trim().to_lowercase() != "n" {
-            tasks::open::open_task_in_editor(task_path)?;
-        } else {
-            let mut input = String::new();
-            println!("Do you want to navigate to the task directory? [Y/n]");
-
-            std::io::stdin().read_line(&mut input)?;
-            if input.trim().to_lowercase() != "n" {
-                let task_dir = task_path.parent().unwrap();
-                let _ = open::that_in_background(task_dir);
-
-                if input.trim().to_lowercase() != "n" {
-                    let task_dir = task_path.parent().unwrap();
-                    println!("To navigate to the task directory, run the following command in your terminal:");
-                    println!("cd {}", task_dir.display());
+    if ctx.config()?.settings.output_format == OutputFormat::Json {
+        let info = serde_json::json!({
+            "taskid": task.taskid,
+            "shortname": task.task_description.shortname,
+            "title": task.task_description.title,
+            "orderBy": task.order_by,
+            "solved": meta.solved_task_ids().contains(&task.taskid),
+            "archived": meta.is_archived(task.taskid),
+            "prerequisites": prerequisites.iter().map(|task| task.taskid).collect::<Vec<_>>(),
+            "directory": directory,
+            "activity": activity_json(&activity),
+        });
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    let time = |time: Option<DateTime<Utc>>| match time {
+        Some(time) => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        None => "never".to_string(),
+    };
+
+    println!(
+        "{} ({}): {}",
+        task.task_description.shortname.bright_blue(),
+        task.taskid,
+        task.task_description.title
+    );
+    println!("Status:        {}", task_status(&meta, task));
+    if !prerequisites.is_empty() {
+        println!("Prerequisites: {}", shortnames(&prerequisites));
+    }
+    if let Some(directory) = directory {
+        println!("Directory:     {}", directory.display());
+    }
+    println!("First started: {}", time(activity.first_started));
+    println!(
+        "Submissions:   {}, {} failed",
+        activity.submissions, activity.failures
+    );
+    println!("Last attempt:  {}", time(activity.last_attempt));
+    if activity.solved_at.is_some() {
+        println!("Solved:        {}", time(activity.solved_at));
+    }
+    println!(
+        "Active time:   ~{} (estimated from your starts and submissions)",
+        format_duration(activity.active_time())
+    );
+
+    Ok(())
+}
+
+fn task_status(meta: &config::meta::Meta, task: &Task) -> colored::ColoredString {
+    if meta.is_archived(task.taskid) {
+        "Archived".yellow()
+    } else if meta.solved_task_ids().contains(&task.taskid) {
+        "Completed".green()
+    } else {
+        match meta.missing_prerequisites(task.taskid).as_slice() {
+            [] => "Not yet completed".red(),
+            missing => format!("Locked, solve {} first", shortnames(missing)).bright_black(),
+        }
+    }
+}
+
+fn activity_json(activity: &TaskActivity) -> serde_json::Value {
+    serde_json::json!({
+        "firstStarted": activity.first_started,
+        "submissions": activity.submissions,
+        "failures": activity.failures,
+        "lastAttempt": activity.last_attempt,
+        "solvedAt": activity.solved_at,
+        "activeSeconds": activity.active_seconds,
+    })
+}
+
+/// e.g. "1h 05m" or "12m"
+fn format_duration(duration: chrono::Duration) -> String {
+    let minutes = duration.num_minutes();
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// Prints the course as a dependency graph, by default in the DOT format
+pub fn graph(ctx: &Context, format: Option<GraphFormat>) -> anyhow::Result<()> {
+    ensure_configured(ctx)?;
+    ensure_tasks_init(ctx)?;
+
+    let format = format.unwrap_or(match ctx.config()?.settings.output_format {
+        OutputFormat::Json => GraphFormat::Json,
+        OutputFormat::Text => GraphFormat::Dot,
+    });
+    let meta = config::meta::Meta::load(ctx)?;
+    print!("{}", TaskGraph::new(&meta).render(format)?);
+
+    Ok(())
+}
+
+pub async fn start_task(ctx: &Context, task_id: Option<usize>) -> anyhow::Result<()> {
+    ensure_fully_setup(ctx).await?;
+    let meta = config::meta::Meta::load(ctx)?;
+
+    let task_id = match task_id.or(meta.next_task().map(|task| task.taskid)) {
+        Some(task_id) => task_id,
+        None => {
+            // without a next task, everything left is locked, e.g. by prerequisites that form a cycle
+            let locked = meta
+                .tasks()
+                .iter()
+                .filter(|task| {
+                    !meta.is_archived(task.taskid) && !meta.solved_task_ids().contains(&task.taskid)
+                })
+                .collect::<Vec<_>>();
+
+            if locked.is_empty() {
+                println!("{}", "You have solved all tasks, well done!".green());
+            } else {
+                println!("Every task that is left is still locked:");
+                for task in locked {
+                    println!(
+                        "  {} waits on {}",
+                        task.task_description.shortname.bright_blue(),
+                        shortnames(&meta.missing_prerequisites(task.taskid))
+                    );
                 }
             }
+            return Ok(());
+        }
+    };
+
+    let missing = meta.missing_prerequisites(task_id);
+    if !missing.is_empty() {
+        println!(
+            "{} this task is locked until you solve {}.",
+            "Note:".yellow(),
+            shortnames(&missing)
+        );
+    }
+
+    if let Some(task_path) = meta.get_task_path(task_id) {
+        let mut locked = config::meta::Meta::lock(ctx)?;
+        locked.activity_mut(task_id).record_start(Utc::now());
+        locked.store()?;
+
+        let editor = ctx.config()?.settings.editor;
+        let question = match &editor {
+            Some(editor) => format!("Do you want to open the task with `{}`?", editor),
+            None => "Do you want to open the task in your default editor?".to_string(),
+        };
+
+        if prompt_for_consent(&question) {
+            tasks::open::open_task_in_editor(task_path, editor.as_deref())?;
+        } else if prompt_for_consent("Do you want to navigate to the task directory?") {
+            let task_dir = task_path.parent().unwrap();
+            let _ = open::that_in_background(task_dir);
+
+            println!(
+                "To navigate to the task directory, run the following command in your terminal:"
+            );
+            println!("cd {}", task_dir.display());
         }
     } else {
         println!("Task with ID {} not found.", task_id);
@@ -226,8 +1277,16 @@ pub async fn start_task(task_id: Option<usize>) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn ensure_configured() -> anyhow::Result<()> {
-    let cfg = Config::load()?;
+/// e.g. "hello, loops"
+fn shortnames(tasks: &[&Task]) -> String {
+    tasks
+        .iter()
+        .map(|task| task.task_description.shortname.as_str())
+        .join(", ")
+}
+
+fn ensure_configured(ctx: &Context) -> anyhow::Result<()> {
+    let cfg = ctx.config()?;
 
     if cfg.user.is_empty() || cfg.course.is_empty() || cfg.host.is_empty() {
         let binary_name = std::env::args().next().unwrap();
@@ -247,34 +1306,139 @@ fn ensure_configured() -> anyhow::Result<()> {
             eprintln!("{}", stderr);
         }
 
-        anyhow::bail!("{}", "Please configure the CLI first.".bright_red());
+        let msg = if cfg.profile == DEFAULT_PROFILE {
+            "Please configure the CLI first.".to_string()
+        } else {
+            format!("Please configure the profile `{}` first.", cfg.profile)
+        };
+        anyhow::bail!("{}", msg.bright_red());
     }
 
     Ok(())
 }
 
 /// Ensure that Meta has been set up, this is the case after a sync
-fn ensure_tasks_init() -> anyhow::Result<()> {
-    let meta = config::meta::Meta::load()?;
+fn ensure_tasks_init(ctx: &Context) -> anyhow::Result<()> {
+    let meta = config::meta::Meta::load(ctx)?;
 
     if meta.tasks().is_empty() {
         anyhow::bail!("{}", "Please sync the exercises first.".bright_red());
     }
 
+    ensure_workspace_location(ctx)
+}
+
+/// Offers to move the synced tasks if the `workspace_root` setting was changed since the last sync.
+/// If the user declines, the tasks stay where they are and the meta keeps pointing there.
+/// That's remembered, so we only ask again once the workspace root changes again.
+fn ensure_workspace_location(ctx: &Context) -> anyhow::Result<()> {
+    let meta = config::meta::Meta::load(ctx)?;
+    if meta.tasks().is_empty() {
+        return Ok(());
+    }
+
+    // `--workspace` and SBCLI_EXERCISE_DIR only apply to a single invocation, that's no reason to move anything
+    let cfg = ctx.config()?;
+    if cfg.is_overridden("workspace_root") {
+        return Ok(());
+    }
+
+    let target = ctx.workspace_root(&cfg.profile)?;
+    if meta.directory_dir() == target || meta.declined_path() == Some(target.as_path()) {
+        return Ok(());
+    }
+
+    if !is_interactive() {
+        eprintln!(
+            "The workspace root changed, but your tasks stay in {} until you move them from a terminal.",
+            meta.directory_dir().display()
+        );
+        return Ok(());
+    }
+
+    let question = format!(
+        "The workspace root changed. Do you want to move your tasks from {} to {}?",
+        meta.directory_dir().display().to_string().bright_blue(),
+        target.display().to_string().bright_blue()
+    );
+    if prompt_for_consent_default_no(&question) {
+        // not locked while asking, so check whether another sbcli moved it in the meantime
+        let meta = config::meta::Meta::lock(ctx)?;
+        if meta.directory_dir() != target {
+            tasks::files::move_workspace(meta, &target)?;
+        }
+        println!("Moved the workspace to {}", target.display());
+    } else {
+        let mut meta = config::meta::Meta::lock(ctx)?;
+        meta.set_declined_path(Some(target));
+        let meta = meta.store()?;
+
+        let command_str = format!(
+            "`{} config set workspace_root <dir>`",
+            env!("CARGO_PKG_NAME")
+        )
+        .on_bright_black();
+        println!(
+            "Keeping the tasks in {}. To move them later, run {}.",
+            meta.directory_dir().display().to_string().bright_blue(),
+            command_str
+        );
+    }
+
+    Ok(())
+}
+
+/// Setting the workspace root explicitly asks again, even for a location the user declined before
+fn ask_again_for_workspace_location(ctx: &Context) -> anyhow::Result<()> {
+    let mut meta = config::meta::Meta::lock(ctx)?;
+    if meta.declined_path().is_some() {
+        meta.set_declined_path(None);
+        meta.store()?;
+    }
     Ok(())
 }
 
-async fn ensure_configured_and_auth() -> anyhow::Result<()> {
-    ensure_configured()?;
-    ensure_auth().await?;
+async fn ensure_configured_and_auth(ctx: &Context) -> anyhow::Result<()> {
+    ensure_configured(ctx)?;
+    ensure_auth(ctx).await?;
 
     Ok(())
 }
 
-async fn ensure_fully_setup() -> anyhow::Result<()> {
-    ensure_configured()?;
-    ensure_tasks_init()?;
-    ensure_auth().await?;
+/// Everything a command needs to work on the synced tasks.
+/// Offline, the session can't be checked, so commands work with what's cached instead.
+/// Returns the client for the command. It's offline with `--offline`, or once SmartBeans turned out to be unreachable,
+/// be it while checking the session, sending queued submissions or later on.
+async fn ensure_fully_setup(ctx: &Context) -> anyhow::Result<requests::ApiClient> {
+    ensure_configured(ctx)?;
+    ensure_tasks_init(ctx)?;
+
+    let reachable = if ctx.offline {
+        false
+    } else {
+        match ensure_auth(ctx).await {
+            Err(e) if requests::is_unreachable(&e) => false,
+            Err(e) => return Err(e),
+            Result::Ok(()) => true,
+        }
+    };
+
+    let client = requests::ApiClient::new(ctx)?;
+    if reachable {
+        flush_queue_quietly(ctx, &client).await;
+    } else {
+        client.set_offline();
+    }
+    Ok(client)
+}
 
+/// For commands that can't do anything useful with cached data
+fn ensure_online(ctx: &Context, action: &str) -> anyhow::Result<()> {
+    if ctx.offline {
+        anyhow::bail!(
+            "{} needs a connection to SmartBeans, run it without `--offline`.",
+            action
+        );
+    }
     Ok(())
 }
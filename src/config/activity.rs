@@ -0,0 +1,82 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Gaps between two events that are longer than this count as a break, not as time spent on the task
+const IDLE_GAP_MINUTES: i64 = 30;
+
+/// What the user did locally on a task, kept in the meta next to the task
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaskActivity {
+    /// When the task was first opened with `sbcli start`
+    pub first_started: Option<DateTime<Utc>>,
+    pub submissions: usize,
+    pub failures: usize,
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// When a submission from this machine first solved the task
+    pub solved_at: Option<DateTime<Utc>>,
+    /// Estimated from the gaps between starts and submissions, breaks are left out
+    pub active_seconds: i64,
+    last_event: Option<DateTime<Utc>>,
+}
+
+impl TaskActivity {
+    pub fn record_start(&mut self, now: DateTime<Utc>) {
+        self.first_started.get_or_insert(now);
+        self.record_event(now);
+    }
+
+    pub fn record_submission(&mut self, successful: bool, now: DateTime<Utc>) {
+        self.record_event(now);
+        self.submissions += 1;
+        self.last_attempt = Some(now);
+
+        if !successful {
+            self.failures += 1;
+        } else if self.solved_at.is_none() {
+            self.solved_at = Some(now);
+        }
+    }
+
+    pub fn active_time(&self) -> Duration {
+        Duration::seconds(self.active_seconds)
+    }
+
+    fn record_event(&mut self, now: DateTime<Utc>) {
+        if let Some(last_event) = self.last_event {
+            let gap = now - last_event;
+            if gap > Duration::zero() && gap <= Duration::minutes(IDLE_GAP_MINUTES) {
+                self.active_seconds += gap.num_seconds();
+            }
+        }
+        self.last_event = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_activity() {
+        let start = Utc::now();
+        let mut activity = TaskActivity::default();
+
+        activity.record_start(start);
+        activity.record_submission(false, start + Duration::minutes(10));
+        // after a long break
+        activity.record_start(start + Duration::hours(3));
+        activity.record_submission(true, start + Duration::hours(3) + Duration::minutes(5));
+        activity.record_submission(true, start + Duration::hours(4));
+
+        assert_eq!(activity.first_started, Some(start));
+        assert_eq!(activity.submissions, 3);
+        assert_eq!(activity.failures, 1);
+        assert_eq!(
+            activity.solved_at,
+            Some(start + Duration::hours(3) + Duration::minutes(5))
+        );
+        assert_eq!(activity.last_attempt, Some(start + Duration::hours(4)));
+        assert_eq!(activity.active_time(), Duration::minutes(15));
+    }
+}
@@ -3,15 +3,25 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use confy::ConfyError;
 use serde::{Deserialize, Serialize};
 
+use anyhow::Context as _;
+
 use crate::{
     requests::ApiClient,
-    tasks::{files::make_task_path, models::Task},
+    tasks::{files::make_task_path, marker::TaskMarker, models::Task},
+    util::did_you_mean,
+};
+
+use super::{
+    activity::TaskActivity,
+    profile::DEFAULT_PROFILE,
+    versioning::{self, Versioned},
+    workspace_root, Config, Context, Locked,
 };
 
-use super::{APP_NAME, DIRECTORY_DIR_NAME, META_FILE_NAME};
+/// Version of the meta file format, see [`versioning`]
+pub const META_VERSION: u32 = 1;
 
 /// `TaskDirectory` maintains the task-related data for the CLI app.
 /// It contains the tasks, the order in which they should be solved and/or displayed, their workspace paths, and the solved tasks.
@@ -27,53 +37,33 @@ pub struct TaskDirectory {
     order: HashMap<usize, usize>,
     /// <task_id, workspace_path>
     directory: HashMap<usize, PathBuf>,
+    /// Tasks that were removed on the server. They are kept, so the code in their directories stays reachable.
+    #[serde(default)]
+    archived_tasks_ids: Vec<usize>,
+    /// <task_id, activity>, only for tasks that were worked on with sbcli
+    #[serde(default)]
+    activity: HashMap<usize, TaskActivity>,
+    /// A workspace root the user didn't want the tasks moved to, so we don't ask again on every command
+    #[serde(default)]
+    declined_path: Option<PathBuf>,
 }
 
 impl Default for TaskDirectory {
     fn default() -> Self {
-        // on windows, the default exercises directory is %USERPROFILE%\sbcli\<DIR_NAME>
-        // on linux, the default exercises directory is $HOME/sbcli/
-        let path = dirs::home_dir()
-            .map(|mut p| {
-                p.push(APP_NAME);
-                p.push(DIRECTORY_DIR_NAME);
-                p
-            })
-            .unwrap_or_else(|| PathBuf::from(APP_NAME));
-
         Self {
-            path,
+            path: workspace_root(DEFAULT_PROFILE, None),
             tasks: Vec::new(),
             solved_tasks_ids: Vec::new(),
             order: HashMap::new(),
             directory: HashMap::new(),
+            archived_tasks_ids: Vec::new(),
+            activity: HashMap::new(),
+            declined_path: None,
         }
     }
 }
 
 impl TaskDirectory {
-    pub fn new(tasks: &[Task]) -> Self {
-        let d = Self::default();
-
-        let tasks = tasks.to_owned();
-        let order = Self::get_order(&tasks);
-        let directory = tasks
-            .iter()
-            .map(|task| {
-                let (dir_path, _) = make_task_path(task, &d.path).unwrap();
-                (task.taskid, dir_path)
-            })
-            .collect::<HashMap<usize, PathBuf>>();
-
-        Self {
-            path: d.path,
-            tasks,
-            order,
-            directory,
-            ..d
-        }
-    }
-
     fn get_order(tasks: &[Task]) -> HashMap<usize, usize> {
         tasks
             .iter()
@@ -82,29 +72,66 @@ impl TaskDirectory {
             .collect()
     }
 
-    /// Returns the ID of the next task to be solved
-    /// If all tasks have been solved, returns 0
-    fn get_next_task(&self) -> usize {
-        let highest_solved_order = self
-            .solved_tasks_ids
+    /// Returns the ID of the next task to be solved: the lowest-ordered task that is unlocked and not solved yet.
+    /// Archived tasks are skipped. Returns `None` if there is no such task.
+    fn get_next_task(&self) -> Option<usize> {
+        self.tasks
             .iter()
-            .filter_map(|task_id| self.order.get(task_id))
-            .max();
+            .filter(|task| self.order.contains_key(&task.taskid))
+            .filter(|task| !self.solved_tasks_ids.contains(&task.taskid))
+            .filter(|task| self.missing_prerequisites(task).is_empty())
+            .min_by_key(|task| (task.order_by, self.order[&task.taskid]))
+            .map(|task| task.taskid)
+    }
 
-        if let Some(highest_solved_order) = highest_solved_order {
-            let next_task = self
-                .order
+    /// Resolves the prerequisites of a task, given by short name or ID, to the tasks they refer to.
+    /// Prerequisites that aren't part of the course (anymore) can't be solved, so they are ignored.
+    fn prerequisites<'a>(&'a self, task: &'a Task) -> impl Iterator<Item = &'a Task> {
+        task.prerequisites.iter().filter_map(|prerequisite| {
+            self.tasks
                 .iter()
-                .filter(|(_, order)| **order > *highest_solved_order)
-                .min_by_key(|(_, order)| **order)
-                .map(|(task_id, _)| *task_id);
+                .filter(|other| self.order.contains_key(&other.taskid))
+                .find(|other| {
+                    other
+                        .task_description
+                        .shortname
+                        .eq_ignore_ascii_case(prerequisite)
+                        || other.taskid.to_string() == *prerequisite
+                })
+        })
+    }
 
-            if let Some(next_task) = next_task {
-                return next_task;
-            }
-        }
+    /// The prerequisites of a task that haven't been solved yet, the task is unlocked once this is empty
+    fn missing_prerequisites<'a>(&'a self, task: &'a Task) -> Vec<&'a Task> {
+        self.prerequisites(task)
+            .filter(|prerequisite| !self.solved_tasks_ids.contains(&prerequisite.taskid))
+            .collect()
+    }
+}
 
-        0
+/// What changed in the task list since the last sync
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    /// Nothing was synced before, so every task is new
+    pub first_sync: bool,
+    pub added: Vec<usize>,
+    pub updated: Vec<usize>,
+    pub archived: Vec<usize>,
+    /// Task directories whose name changed with the task, <task_id, old_path, new_path>
+    pub moved: Vec<(usize, PathBuf, PathBuf)>,
+    /// Renamed tasks that keep their old directory, because the new one already exists, <task_id, old_path, new_path>
+    pub not_moved: Vec<(usize, PathBuf, PathBuf)>,
+    /// Tasks whose files couldn't be written or whose submissions couldn't be downloaded, <task_id, error>
+    pub failed: Vec<(usize, String)>,
+}
+
+impl SyncSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.updated.is_empty()
+            && self.archived.is_empty()
+            && self.moved.is_empty()
+            && self.not_moved.is_empty()
     }
 }
 
@@ -114,46 +141,105 @@ impl TaskDirectory {
 ///
 /// The `Meta` struct also contains a `TaskDirectory`, which is responsible for maintaining task-specific data.
 /// The `Meta` struct provides methods for loading, saving, and updating the state.
-#[derive(Debug, Default, Serialize, Deserialize)]
-
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Meta {
+    /// Read by [`versioning::load`] before the rest of the file
+    #[serde(skip_deserializing, default = "meta_version")]
+    version: u32,
     pub total_tasks: usize,
     pub solved_tasks: usize,
+    /// 0 if there is no unlocked task left, see [`Meta::next_task`]
     pub next_task_id: usize,
     task_directory: TaskDirectory,
 }
 
-impl Meta {
-    pub fn new(tasks: &[Task]) -> Self {
-        let task_directory = TaskDirectory::new(tasks);
-
+impl Default for Meta {
+    fn default() -> Self {
         Self {
-            total_tasks: tasks.len(),
-            task_directory,
-            ..Default::default()
+            version: META_VERSION,
+            total_tasks: 0,
+            solved_tasks: 0,
+            next_task_id: 0,
+            task_directory: TaskDirectory::default(),
         }
     }
+}
+
+fn meta_version() -> u32 {
+    META_VERSION
+}
+
+impl Versioned for Meta {
+    const VERSION: u32 = META_VERSION;
+    const NAME: &'static str = "meta";
+}
+
+impl Meta {
+    /// A meta as after the first sync of `tasks`, syncs go through [`Meta::merge_tasks`]
+    #[cfg(test)]
+    pub fn new(tasks: &[Task], path: PathBuf) -> Self {
+        let mut meta = Self::default();
+        meta.task_directory.path = path;
+        meta.merge_tasks(tasks).unwrap();
+        meta
+    }
 
+    #[allow(dead_code)]
     pub fn task_directory(&self) -> &TaskDirectory {
         &self.task_directory
     }
 
-    pub fn load() -> Result<Self, ConfyError> {
-        confy::load::<Self>(APP_NAME, META_FILE_NAME)
+    /// Loads the meta of the current profile for reading.
+    /// Commands that change it use [`Meta::lock`] instead, so they don't overwrite each other.
+    pub fn load(ctx: &Context) -> anyhow::Result<Self> {
+        let profile = ctx.profile()?;
+        let mut meta = versioning::load::<Self>(&ctx.meta_path(&profile))?;
+        meta.init(ctx, &profile)?;
+        Ok(meta)
     }
 
-    pub fn save(&self) -> Result<(), ConfyError> {
-        confy::store(APP_NAME, META_FILE_NAME, self)
+    /// Loads the meta of the current profile for changing it, see [`Locked`].
+    /// Hold it only as long as needed, other sbcli processes wait for it.
+    pub fn lock(ctx: &Context) -> anyhow::Result<Locked<Self>> {
+        let profile = ctx.profile()?;
+        let mut meta = versioning::lock::<Self>(&ctx.meta_path(&profile))?;
+        meta.init(ctx, &profile)?;
+        Ok(meta)
+    }
+
+    fn init(&mut self, ctx: &Context, profile: &str) -> anyhow::Result<()> {
+        // nothing has been synced yet, so point the workspace at the profile's directory
+        if self.tasks().is_empty() {
+            self.task_directory.path = ctx.workspace_root(profile)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the meta file of a profile, the next load starts from scratch
+    pub fn remove(ctx: &Context, profile: &str) -> anyhow::Result<()> {
+        let path = ctx.meta_path(profile);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
     }
 
     /// uuugh, this is a mess
     /// the whole load/save/update thing needs to be reworked
     /// This function in particular should just be part of some kind of init flow, since we update progress on every submit by adding the task id
-    pub async fn update_progress(client: &ApiClient) -> anyhow::Result<()> {
+    pub async fn update_progress(ctx: &Context, client: &ApiClient) -> anyhow::Result<()> {
         let solved_tasks = client.get_solved_task_ids().await?;
-        let mut meta = Self::load()?;
-        meta.set_solved_tasks_ids(solved_tasks);
-        Ok(meta.save()?)
+        let mut meta = Self::lock(ctx)?;
+        if client.is_offline() {
+            // the cached progress may predate tasks solved since, so it can only add to what we know
+            for task_id in solved_tasks {
+                meta.add_solved_task_id(task_id);
+            }
+        } else {
+            meta.set_solved_tasks_ids(solved_tasks);
+        }
+        meta.store()?;
+        Ok(())
     }
 
     pub fn tasks(&self) -> &[Task] {
@@ -164,48 +250,196 @@ impl Meta {
         &self.task_directory.path
     }
 
-    pub fn get_task_path(&self, task_id: usize) -> Option<&PathBuf> {
-        self.task_directory.directory.get(&task_id)
+    /// The workspace root the user declined to move the tasks to, see [`Meta::set_declined_path`]
+    pub fn declined_path(&self) -> Option<&Path> {
+        self.task_directory.declined_path.as_deref()
+    }
+
+    /// Remembers that the tasks should stay where they are, even though the workspace root points to `path`.
+    /// `None` asks again.
+    pub fn set_declined_path(&mut self, path: Option<PathBuf>) {
+        self.task_directory.declined_path = path;
     }
 
-    fn find_workspace_directory(path: &Path) -> Option<PathBuf> {
-        let mut current_path = path.canonicalize().ok()?;
+    /// Points the workspace at a new directory, keeping each task's folder relative to it.
+    /// This only rewrites the meta, the files have to be moved separately.
+    pub fn relocate(&mut self, path: PathBuf) {
+        self.task_directory.declined_path = None;
+        let old_path = std::mem::replace(&mut self.task_directory.path, path);
 
-        loop {
-            // Check if the current path's parent directory is named DIRECTORY_DIR_NAME
-            // TODO: improve this check
-            if let Some(parent) = current_path.parent() {
-                if parent.file_name().unwrap_or_default() == DIRECTORY_DIR_NAME {
-                    return Some(current_path);
+        for dir in self.task_directory.directory.values_mut() {
+            if let Ok(relative) = dir.strip_prefix(&old_path) {
+                *dir = self.task_directory.path.join(relative);
+            }
+        }
+    }
+
+    /// Brings the tasks in line with the ones on the server.
+    /// New tasks are added, changed ones updated, and tasks that disappeared are archived instead of removed.
+    /// This only rewrites the meta, renamed task directories have to be moved separately, see [`SyncSummary::moved`].
+    pub fn merge_tasks(&mut self, tasks: &[Task]) -> anyhow::Result<SyncSummary> {
+        let dir = &mut self.task_directory;
+        let mut summary = SyncSummary {
+            first_sync: dir.tasks.is_empty(),
+            ..Default::default()
+        };
+
+        for task in tasks {
+            match dir
+                .tasks
+                .iter_mut()
+                .find(|known| known.taskid == task.taskid)
+            {
+                None => summary.added.push(task.taskid),
+                Some(known) => {
+                    if dir.archived_tasks_ids.contains(&task.taskid) {
+                        dir.archived_tasks_ids.retain(|id| *id != task.taskid);
+                        summary.added.push(task.taskid);
+                    } else if known != task {
+                        summary.updated.push(task.taskid);
+                    }
                 }
             }
 
-            // Move up one level in the directory structure
-            current_path = match current_path.parent() {
-                Some(parent_path) => parent_path.to_path_buf(),
-                None => break,
-            };
+            let (dir_path, _) = make_task_path(task, &dir.path)?;
+            if let Some(old_path) = dir.directory.insert(task.taskid, dir_path.clone()) {
+                if old_path != dir_path {
+                    summary.moved.push((task.taskid, old_path, dir_path));
+                }
+            }
         }
 
-        None
+        // the server's order first, archived tasks at the end
+        let mut merged = tasks.to_vec();
+        for known in dir.tasks.drain(..) {
+            if tasks.iter().any(|task| task.taskid == known.taskid) {
+                continue;
+            }
+            if !dir.archived_tasks_ids.contains(&known.taskid) {
+                dir.archived_tasks_ids.push(known.taskid);
+                summary.archived.push(known.taskid);
+            }
+            merged.push(known);
+        }
+
+        dir.tasks = merged;
+        dir.order = TaskDirectory::get_order(tasks);
+        self.total_tasks = tasks.len();
+        self.update();
+
+        Ok(summary)
     }
 
-    /// TODO this is a bit hacky, but it works. Fix it tho.
-    pub fn get_task_id_from_workspace(&self, task_path: &Path) -> Option<usize> {
-        let task_path = task_path
-            .canonicalize()
-            .expect("Failed to get canonical path");
+    /// The task to work on next, `None` if every task is solved or still locked.
+    /// Computed from the current state, `next_task_id` may have been written by an older sbcli.
+    pub fn next_task(&self) -> Option<&Task> {
+        self.task(self.task_directory.get_next_task()?)
+    }
+
+    /// The tasks that have to be solved before this one
+    pub fn prerequisites(&self, task_id: usize) -> Vec<&Task> {
+        match self.task(task_id) {
+            Some(task) => self.task_directory.prerequisites(task).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The unsolved tasks that have to be solved before this one
+    pub fn missing_prerequisites(&self, task_id: usize) -> Vec<&Task> {
+        match self.task(task_id) {
+            Some(task) => self.task_directory.missing_prerequisites(task),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn activity(&self, task_id: usize) -> Option<&TaskActivity> {
+        self.task_directory.activity.get(&task_id)
+    }
+
+    pub fn activity_mut(&mut self, task_id: usize) -> &mut TaskActivity {
+        self.task_directory.activity.entry(task_id).or_default()
+    }
+
+    /// Whether the task was removed on the server
+    pub fn is_archived(&self, task_id: usize) -> bool {
+        self.task_directory.archived_tasks_ids.contains(&task_id)
+    }
+
+    pub fn task(&self, task_id: usize) -> Option<&Task> {
+        self.tasks().iter().find(|task| task.taskid == task_id)
+    }
+
+    /// Looks up a task by its ID or short name
+    pub fn find_task(&self, query: &str) -> anyhow::Result<&Task> {
+        let found = match query.parse::<usize>() {
+            Ok(task_id) => self.task(task_id),
+            Err(_) => self
+                .tasks()
+                .iter()
+                .find(|task| task.task_description.shortname.eq_ignore_ascii_case(query)),
+        };
+
+        found.ok_or_else(|| {
+            let shortnames = self
+                .tasks()
+                .iter()
+                .map(|task| task.task_description.shortname.as_str());
+            let hint = match did_you_mean(query, shortnames) {
+                Some(suggestion) => format!(" Did you mean `{}`?", suggestion),
+                None => String::new(),
+            };
+            anyhow::anyhow!(
+                "There is no task `{}`.{} Run `{} list` to see all tasks.",
+                query,
+                hint,
+                env!("CARGO_PKG_NAME")
+            )
+        })
+    }
+
+    pub fn get_task_path(&self, task_id: usize) -> Option<&PathBuf> {
+        self.task_directory.directory.get(&task_id)
+    }
 
-        let workspace_path =
-            Self::find_workspace_directory(&task_path).expect("Failed to find workspace directory");
+    /// Points a task at another directory, e.g. when it keeps its old one after being renamed
+    pub fn set_task_path(&mut self, task_id: usize, path: PathBuf) {
+        self.task_directory.directory.insert(task_id, path);
+    }
+
+    /// Finds the task a file belongs to.
+    /// The marker file of its task directory decides, so this works wherever the directory was moved.
+    /// Workspaces synced before there were markers are matched against the known task directories.
+    pub fn find_task_id(&self, cfg: &Config, path: &Path) -> anyhow::Result<usize> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Can't find {}", path.display()))?;
+
+        if let Some((_, marker)) = TaskMarker::find(&path) {
+            if !marker.matches(cfg) {
+                anyhow::bail!(
+                    "{} belongs to the course `{}` on {}, but the current profile uses `{}` on {}. Use `--profile` to pick another profile.",
+                    path.display(),
+                    marker.course,
+                    marker.host,
+                    cfg.course,
+                    cfg.host
+                );
+            }
+            return Ok(marker.task_id);
+        }
 
         self.task_directory
             .directory
             .iter()
-            .find(|(_, path)| {
-                path.canonicalize().expect("Failed to get canonical path") == workspace_path
+            .find(|(_, dir)| {
+                dir.canonicalize()
+                    .map(|dir| path.starts_with(dir))
+                    .unwrap_or(false)
             })
             .map(|(task_id, _)| *task_id)
+            .ok_or_else(|| {
+                anyhow::anyhow!("{} is not inside a synced task directory.", path.display())
+            })
     }
 
     pub fn solved_task_ids(&self) -> &Vec<usize> {
@@ -231,7 +465,7 @@ impl Meta {
 
     pub fn update(&mut self) {
         self.solved_tasks = self.task_directory.solved_tasks_ids.len();
-        self.next_task_id = self.task_directory.get_next_task();
+        self.next_task_id = self.task_directory.get_next_task().unwrap_or(0);
     }
 }
 
@@ -239,6 +473,7 @@ impl Meta {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::DIRECTORY_DIR_NAME;
 
     #[test]
     fn test_get_next_task() {
@@ -280,15 +515,111 @@ mod tests {
             },
         ];
 
-        let mut meta = Meta::new(&tasks);
+        let mut meta = Meta::new(&tasks, PathBuf::from(DIRECTORY_DIR_NAME));
 
         assert_eq!(meta.total_tasks, 7);
         assert!(meta.task_directory.solved_tasks_ids.is_empty());
+        assert_eq!(meta.next_task_id, 1);
 
         meta.set_solved_tasks_ids(vec![1, 2, 3]);
         assert_eq!(meta.next_task_id, 4);
 
         meta.set_solved_tasks_ids(vec![1, 2, 3, 4, 6]);
-        assert!(meta.next_task_id == 7 || meta.solved_tasks == 6);
+        assert_eq!(meta.next_task_id, 5);
+
+        meta.set_solved_tasks_ids(vec![1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(meta.next_task_id, 0);
+        assert!(meta.next_task().is_none());
+    }
+
+    #[test]
+    fn test_prerequisites() {
+        let task = Task::fixture;
+        let tasks = vec![
+            task(1, "hello", &[]),
+            task(2, "loops", &["hello", "missing"]),
+            task(3, "pointers", &["loops", "1"]),
+            task(4, "strings", &["hello"]),
+        ];
+        let mut meta = Meta::new(&tasks, PathBuf::from(DIRECTORY_DIR_NAME));
+
+        let waiting_on = |meta: &Meta, task_id| {
+            meta.missing_prerequisites(task_id)
+                .iter()
+                .map(|task| task.taskid)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(waiting_on(&meta, 2), vec![1]);
+        assert_eq!(waiting_on(&meta, 3), vec![2, 1]);
+
+        // the next task has to be unlocked, even if a locked one comes first
+        meta.set_solved_tasks_ids(vec![1, 2]);
+        assert_eq!(meta.next_task_id, 3);
+        meta.set_solved_tasks_ids(vec![1, 3]);
+        assert_eq!(meta.next_task_id, 2);
+        assert!(waiting_on(&meta, 4).is_empty());
+    }
+
+    #[test]
+    fn test_merge_tasks() {
+        let task = |taskid, order_by, shortname| Task {
+            order_by,
+            ..Task::fixture(taskid, shortname, &[])
+        };
+        let mut meta = Meta::new(
+            &[
+                task(1, 1, "hello"),
+                task(2, 2, "loops"),
+                task(3, 3, "arrays"),
+            ],
+            PathBuf::from(DIRECTORY_DIR_NAME),
+        );
+        meta.set_solved_tasks_ids(vec![2]);
+        let old_path = meta.get_task_path(3).unwrap().clone();
+
+        let summary = meta
+            .merge_tasks(&[
+                task(1, 1, "hello"),
+                task(3, 2, "arrays"),
+                task(4, 3, "strings"),
+            ])
+            .unwrap();
+
+        assert!(!summary.first_sync);
+        assert_eq!(summary.added, vec![4]);
+        assert_eq!(summary.updated, vec![3]);
+        assert_eq!(summary.archived, vec![2]);
+        assert_eq!(summary.moved.len(), 1);
+        assert_eq!(summary.moved[0].1, old_path);
+
+        // archived tasks and their progress are kept
+        assert!(meta.is_archived(2));
+        assert!(meta.task(2).is_some());
+        assert_eq!(meta.solved_tasks, 1);
+        assert_eq!(meta.total_tasks, 3);
+        assert_eq!(meta.task(3).unwrap().order_by, 2);
+
+        let summary = meta
+            .merge_tasks(&[task(1, 1, "hello"), task(2, 2, "loops")])
+            .unwrap();
+        assert_eq!(summary.added, vec![2]);
+        assert_eq!(summary.archived, vec![3, 4]);
+        assert!(!meta.is_archived(2));
+    }
+
+    #[test]
+    fn test_relocate() {
+        let tasks = vec![Task::fixture(1, "hello", &[])];
+        let mut meta = Meta::new(&tasks, PathBuf::from("old").join(DIRECTORY_DIR_NAME));
+        let old_task_path = meta.get_task_path(1).unwrap().clone();
+
+        let new_path = PathBuf::from("new").join(DIRECTORY_DIR_NAME);
+        meta.relocate(new_path.clone());
+
+        assert_eq!(meta.directory_dir(), new_path);
+        assert_eq!(
+            meta.get_task_path(1).unwrap(),
+            &new_path.join(old_task_path.file_name().unwrap())
+        );
     }
 }
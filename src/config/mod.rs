@@ -1,69 +1,410 @@
+pub mod activity;
 pub mod meta;
-mod settings;
+pub mod overrides;
+pub mod profile;
+pub mod queue;
+pub mod settings;
+mod versioning;
 
-use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::{cache::ResponseCache, credentials::CredentialStoreKind, requests::ApiFlavor};
+
+use self::{
+    overrides::Origin,
+    profile::{Profile, DEFAULT_PROFILE},
+    settings::Settings,
+    versioning::Versioned,
+};
+
+pub use self::versioning::Locked;
+
 pub const APP_NAME: &str = "sbcli";
+/// Version of the config file format, see [`versioning`]
+pub const CONFIG_VERSION: u32 = 2;
 pub const CONFIG_NAME: &str = "config";
+/// Uses another config file for a single invocation, like `--config`
+pub const CONFIG_ENV_VAR: &str = "SBCLI_CONFIG";
 pub const META_FILE_NAME: &str = "meta";
+pub const QUEUE_FILE_NAME: &str = "queue";
 pub const DIRECTORY_DIR_NAME: &str = "tasks";
-// pub const SESSION_DURATION_SECONDS: usize = 60 * 60 * 24 * 7 * 4; // 1 month
+/// Directory next to the config file that holds the cached responses of each profile
+pub const CACHE_DIR_NAME: &str = "cache";
+/// Assumed session length for tokens that don't tell us when they expire
 pub const SESSION_DURATION_SECONDS: usize = 60 * 60 * 12; // 12 hours
+pub const DEFAULT_EXPIRY_MARGIN_SECONDS: u64 = 60 * 5; // 5 minutes
+/// Overrides the `workspace_root` setting
+pub const WORKSPACE_ENV_VAR: &str = "SBCLI_EXERCISE_DIR";
+
+/// What a single invocation works with: which config file, which profile and which workspace.
+/// It's built once from the command line and passed to everything that loads or stores state,
+/// so overrides like `--config` never leak into the files of other invocations.
+#[derive(Clone, Debug)]
+pub struct Context {
+    /// The config file, the meta files are kept next to it
+    pub config_path: PathBuf,
+    /// Takes precedence over the active profile in the config file
+    profile: Option<String>,
+    /// Takes precedence over the `workspace_root` setting
+    workspace: Option<PathBuf>,
+    /// Work with cached responses instead of connecting to SmartBeans
+    pub offline: bool,
+}
+
+impl Context {
+    pub fn new(
+        config_path: Option<PathBuf>,
+        profile: Option<String>,
+        workspace: Option<PathBuf>,
+        offline: bool,
+    ) -> anyhow::Result<Self> {
+        let config_path = match config_path {
+            Some(path) => path,
+            None => confy::get_configuration_file_path(APP_NAME, CONFIG_NAME)?,
+        };
+        if let Some(profile) = &profile {
+            profile::check_name(profile)?;
+        }
+
+        Ok(Self {
+            config_path,
+            profile,
+            workspace,
+            offline,
+        })
+    }
+
+    pub fn load_file(&self) -> anyhow::Result<ConfigFile> {
+        ConfigFile::load_path(&self.config_path)
+    }
+
+    /// Loads the config file for changing it, see [`Locked`]
+    pub fn lock_file(&self) -> anyhow::Result<Locked<ConfigFile>> {
+        ConfigFile::lock_path(&self.config_path)
+    }
+
+    /// The name of the profile this invocation works with
+    pub fn profile(&self) -> anyhow::Result<String> {
+        match &self.profile {
+            Some(profile) => Ok(profile.clone()),
+            None => {
+                // the config file may have been edited by hand
+                let profile = self.load_file()?.active_profile;
+                profile::check_name(&profile)?;
+                Ok(profile)
+            }
+        }
+    }
+
+    /// Loads the current profile, or an empty one if it hasn't been configured yet
+    pub fn config(&self) -> anyhow::Result<Config> {
+        self.config_for(&self.profile()?)
+    }
+
+    /// Loads a profile with the overrides from the environment and the command line applied
+    pub fn config_for(&self, profile: &str) -> anyhow::Result<Config> {
+        let mut cfg = Config::from_file(&self.load_file()?, profile);
+        cfg.apply_env()?;
+        if let Some(workspace) = &self.workspace {
+            cfg.apply_flag(
+                "workspace_root",
+                "--workspace",
+                &workspace.to_string_lossy(),
+            )?;
+        }
+        Ok(cfg)
+    }
+
+    /// Where the responses for a profile are cached, see [`ResponseCache`]
+    pub fn cache(&self, profile: &str) -> ResponseCache {
+        ResponseCache::new(
+            self.config_path
+                .with_file_name(CACHE_DIR_NAME)
+                .join(profile),
+        )
+    }
 
-/// Application configuration.
+    pub fn meta_path(&self, profile: &str) -> PathBuf {
+        self.config_path
+            .with_file_name(format!("{}.ron", meta_file_name(profile)))
+    }
+
+    /// The submissions of a profile that wait for a connection, see [`queue::SubmissionQueue`]
+    pub fn queue_path(&self, profile: &str) -> PathBuf {
+        self.config_path
+            .with_file_name(format!("{}-{}.ron", QUEUE_FILE_NAME, profile))
+    }
+
+    /// Where the tasks of a profile are synced to, see [`workspace_root`]
+    pub fn workspace_root(&self, profile: &str) -> anyhow::Result<PathBuf> {
+        let settings = self.config()?.settings;
+        Ok(workspace_root(profile, settings.workspace_root.as_deref()))
+    }
+}
+
+/// The config file as stored on disk, holding all profiles
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfigFile {
+    /// Read by [`versioning::load`] before the rest of the file
+    #[serde(skip_deserializing)]
+    pub version: u32,
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, Profile>,
+    pub credential_store: CredentialStoreKind,
+    /// Command for the `helper` credential store
+    pub credential_helper: Option<String>,
+    /// Sessions are treated as expired this long before they actually do,
+    /// so we don't start a sync or submission with a token that's about to run out
+    pub expiry_margin_seconds: u64,
+    pub settings: Settings,
+
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::new(),
+            credential_store: CredentialStoreKind::default(),
+            credential_helper: None,
+            expiry_margin_seconds: DEFAULT_EXPIRY_MARGIN_SECONDS,
+            settings: Settings::default(),
+            path: PathBuf::new(),
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn load_path(path: &Path) -> anyhow::Result<Self> {
+        let mut file = versioning::load::<Self>(path)?;
+        file.init(path);
+        Ok(file)
+    }
+
+    pub fn lock_path(path: &Path) -> anyhow::Result<Locked<Self>> {
+        let mut file = versioning::lock::<Self>(path)?;
+        file.init(path);
+        Ok(file)
+    }
+
+    fn init(&mut self, path: &Path) {
+        self.path = path.to_path_buf();
+        if self.active_profile.is_empty() {
+            self.active_profile = DEFAULT_PROFILE.to_string();
+        }
+    }
+}
+
+/// Version 1 of the config, from before profiles existed, with a single account at the top level
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ConfigV1 {
+    host: String,
+    user: String,
+    course: String,
+    token: String,
+    password_command: Option<String>,
+    last_login_time: Option<DateTime<Utc>>,
+    token_expiry: Option<DateTime<Utc>>,
+}
+
+impl Versioned for ConfigFile {
+    const VERSION: u32 = CONFIG_VERSION;
+    const NAME: &'static str = "config";
+
+    /// Unversioned configs stored the sbcli release instead, they only differ in having profiles or not
+    fn legacy_version(contents: &str) -> anyhow::Result<u32> {
+        #[derive(Deserialize)]
+        struct Profiles {
+            #[serde(default, deserialize_with = "is_present")]
+            profiles: bool,
+        }
+
+        fn is_present<'de, D: serde::Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+            serde::de::IgnoredAny::deserialize(d).map(|_| true)
+        }
+
+        let file: Profiles = ron::from_str(contents)?;
+        Ok(if file.profiles { 2 } else { 1 })
+    }
+
+    fn migrate(version: u32, contents: &str) -> anyhow::Result<String> {
+        match version {
+            1 => {
+                let legacy: ConfigV1 = ron::from_str(contents)?;
+                let mut file = Self::default();
+                if !legacy.host.is_empty() {
+                    let profile = Profile {
+                        host: legacy.host,
+                        user: legacy.user,
+                        course: legacy.course,
+                        api_flavor: None,
+                        token: legacy.token,
+                        password_command: legacy.password_command,
+                        last_login_time: legacy.last_login_time,
+                        token_expiry: legacy.token_expiry,
+                    };
+                    file.profiles.insert(DEFAULT_PROFILE.to_string(), profile);
+                }
+                versioning::to_ron(&file)
+            }
+            _ => anyhow::bail!("Unknown config version {}", version),
+        }
+    }
+}
+
+/// Name of the meta file for a profile. The default profile keeps the name from before profiles existed.
+pub fn meta_file_name(profile: &str) -> String {
+    if profile == DEFAULT_PROFILE {
+        META_FILE_NAME.to_string()
+    } else {
+        format!("{}-{}", META_FILE_NAME, profile)
+    }
+}
+
+/// Where the tasks of a profile are synced to.
+/// On windows, the default is %USERPROFILE%\sbcli\<DIR_NAME>, on linux $HOME/sbcli/<DIR_NAME>.
+/// `root` replaces the `sbcli` directory, it comes from `--workspace`, `SBCLI_EXERCISE_DIR` or the `workspace_root` setting.
+/// Other profiles get their own directory in between, e.g. $HOME/sbcli/<profile>/<DIR_NAME>.
+pub fn workspace_root(profile: &str, root: Option<&Path>) -> PathBuf {
+    let mut path = root.map(Path::to_path_buf).unwrap_or_else(|| {
+        dirs::home_dir()
+            .map(|p| p.join(APP_NAME))
+            .unwrap_or_else(|| PathBuf::from(APP_NAME))
+    });
+
+    if profile != DEFAULT_PROFILE {
+        path.push(profile);
+    }
+    path.push(DIRECTORY_DIR_NAME);
+
+    path
+}
+
+/// Application configuration, as seen from the current profile.
+#[derive(Clone, Debug)]
 pub struct Config {
-    pub version: String,
+    /// The config file this was loaded from and is stored to
+    pub path: PathBuf,
+    /// Name of the profile the account fields belong to
+    pub profile: String,
     pub host: String,
     pub user: String,
     pub course: String,
+    /// Detected from the first submissions fetched, e.g. during sync, guessed from the host until then
+    pub api_flavor: Option<ApiFlavor>,
+    /// Only used by the plaintext credential store, see [`crate::credentials`]
     pub token: String,
+    pub credential_store: CredentialStoreKind,
+    /// Command for the `helper` credential store
+    pub credential_helper: Option<String>,
+    /// Command that prints the password, e.g. from a password manager
+    pub password_command: Option<String>,
     pub last_login_time: Option<DateTime<Utc>>,
-    // pub settings: settings::Settings,
+    /// When the session expires, if the server or the token told us
+    pub token_expiry: Option<DateTime<Utc>>,
+    /// Sessions are treated as expired this long before they actually do,
+    /// so we don't start a sync or submission with a token that's about to run out
+    pub expiry_margin_seconds: u64,
+    pub settings: Settings,
+    /// Fields and settings overridden by the environment or flags, see [`overrides`]
+    origins: HashMap<&'static str, Origin>,
+    /// The values they were overridden with, fields that still have them aren't stored
+    override_values: HashMap<&'static str, Option<String>>,
+    /// The values of overridden profile fields in the config file, see [`Config::stored_value`]
+    file_values: HashMap<&'static str, Option<String>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self {
-            version: env!("CARGO_PKG_VERSION").to_string(),
-            host: "".to_string(),
-            user: "".to_string(),
-            course: "".to_string(),
-            token: "".to_string(),
-            last_login_time: None,
-        }
+        Self::from_file(&ConfigFile::default(), DEFAULT_PROFILE)
     }
 }
 
 impl Config {
-    // pub fn init () -> Result<Self, confy::ConfyError> {
-    //     let cfg = Self::load()?;
-    //     Ok(cfg)
-    // }
+    /// Writes the profile back into the config file, creating it if needed.
+    /// Overridden fields keep the value from the file, unless the command changed them.
+    pub fn store(&self) -> anyhow::Result<()> {
+        let mut file = ConfigFile::lock_path(&self.path)?;
+
+        let stored = Config::from_file(&file, &self.profile);
+        let mut cfg = self.clone();
+        for key in self.origins.keys() {
+            if !self.changed_override(key) {
+                cfg.restore_field(key, &stored);
+            }
+        }
 
-    pub fn load() -> Result<Self, confy::ConfyError> {
-        confy::load::<Self>(APP_NAME, CONFIG_NAME)
+        file.credential_store = cfg.credential_store;
+        file.credential_helper = cfg.credential_helper.clone();
+        file.expiry_margin_seconds = cfg.expiry_margin_seconds;
+        file.profiles.insert(cfg.profile.clone(), cfg.to_profile());
+
+        file.store()?;
+        Ok(())
     }
 
-    pub fn store(&self) -> Result<(), confy::ConfyError> {
-        confy::store(APP_NAME, CONFIG_NAME, self)
+    fn from_file(file: &ConfigFile, profile: &str) -> Self {
+        let p = file.profiles.get(profile).cloned().unwrap_or_default();
+
+        Self {
+            path: file.path.clone(),
+            profile: profile.to_string(),
+            host: p.host,
+            user: p.user,
+            course: p.course,
+            api_flavor: p.api_flavor,
+            token: p.token,
+            credential_store: file.credential_store,
+            credential_helper: file.credential_helper.clone(),
+            password_command: p.password_command,
+            last_login_time: p.last_login_time,
+            token_expiry: p.token_expiry,
+            expiry_margin_seconds: file.expiry_margin_seconds,
+            settings: file.settings.clone(),
+            origins: HashMap::new(),
+            override_values: HashMap::new(),
+            file_values: HashMap::new(),
+        }
     }
 
-    pub fn is_token_valid(&self) -> bool {
-        if let Some(last_login_time) = self.last_login_time {
-            let now = Utc::now();
-            let duration = now.signed_duration_since(last_login_time);
-            let seconds = duration.num_seconds() as usize;
-            seconds < SESSION_DURATION_SECONDS
-        } else {
-            false
+    fn to_profile(&self) -> Profile {
+        Profile {
+            host: self.host.clone(),
+            user: self.user.clone(),
+            course: self.course.clone(),
+            api_flavor: self.api_flavor,
+            token: self.token.clone(),
+            password_command: self.password_command.clone(),
+            last_login_time: self.last_login_time,
+            token_expiry: self.token_expiry,
         }
     }
 
-    // pub fn show() -> Result<(), confy::ConfyError> {
-    //     let cfg = Self::load()?;
-    //     println!("{:#?}", cfg);
-    //     Ok(())
-    // }
+    /// When the current session expires.
+    /// Falls back to `SESSION_DURATION_SECONDS` after the last login if the real expiry is unknown.
+    pub fn session_expiry(&self) -> Option<DateTime<Utc>> {
+        self.token_expiry.or_else(|| {
+            self.last_login_time
+                .map(|t| t + Duration::seconds(SESSION_DURATION_SECONDS as i64))
+        })
+    }
+
+    pub fn is_token_valid(&self) -> bool {
+        let margin = Duration::seconds(self.expiry_margin_seconds as i64);
+
+        self.session_expiry()
+            .map(|expiry| Utc::now() + margin < expiry)
+            .unwrap_or(false)
+    }
 }
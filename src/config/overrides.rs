@@ -0,0 +1,344 @@
+use std::fmt::Display;
+
+use anyhow::Context as _;
+use clap::ValueEnum;
+
+use crate::{
+    auth::TOKEN_ENV_VAR,
+    credentials::CredentialStoreKind,
+    requests::{normalize_host, ApiFlavor},
+    util::did_you_mean,
+};
+
+use super::{
+    settings::{possible_values, value_name, SETTINGS},
+    Config,
+};
+
+/// Where the effective value of a config field or setting comes from.
+/// Later layers win: defaults < config file < environment < command line flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File,
+    Env(&'static str),
+    Flag(&'static str),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File => write!(f, "config file"),
+            Origin::Env(var) => write!(f, "environment {}", var),
+            Origin::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// A field of the profile that can be overridden from the environment
+pub struct FieldSpec {
+    pub key: &'static str,
+    pub env: &'static str,
+    pub description: &'static str,
+    /// The accepted values of enum fields
+    pub values: Option<fn() -> String>,
+}
+
+impl FieldSpec {
+    /// The description for `sbcli config list`, with the accepted values if there's a fixed set
+    pub fn describe(&self) -> String {
+        match self.values {
+            Some(values) => format!("{} ({})", self.description, values()),
+            None => self.description.to_string(),
+        }
+    }
+}
+
+pub const CONFIG_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        key: "host",
+        env: "SBCLI_HOST",
+        description: "The SmartBeans instance",
+        values: None,
+    },
+    FieldSpec {
+        key: "user",
+        env: "SBCLI_USER",
+        description: "Your username",
+        values: None,
+    },
+    FieldSpec {
+        key: "course",
+        env: "SBCLI_COURSE",
+        description: "The course you work on",
+        values: None,
+    },
+    FieldSpec {
+        key: "api_flavor",
+        env: "SBCLI_API_FLAVOR",
+        description: "Which SmartBeans API the host serves",
+        values: Some(possible_values::<ApiFlavor>),
+    },
+    FieldSpec {
+        key: "token",
+        env: TOKEN_ENV_VAR,
+        description: "The session token, only shown if it's kept in the config file",
+        values: None,
+    },
+    FieldSpec {
+        key: "credential_store",
+        env: "SBCLI_CREDENTIAL_STORE",
+        description: "Where the session token is kept",
+        values: Some(possible_values::<CredentialStoreKind>),
+    },
+    FieldSpec {
+        key: "credential_helper",
+        env: "SBCLI_CREDENTIAL_HELPER",
+        description: "Command of the git-style credential helper",
+        values: None,
+    },
+    FieldSpec {
+        key: "password_command",
+        env: "SBCLI_PASSWORD_COMMAND",
+        description: "Command that prints your password",
+        values: None,
+    },
+    FieldSpec {
+        key: "expiry_margin_seconds",
+        env: "SBCLI_EXPIRY_MARGIN_SECONDS",
+        description: "How long before it expires a session is treated as expired",
+        values: None,
+    },
+];
+
+/// Reads an environment variable, treating empty values as unset
+fn env_value(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+impl Config {
+    /// Overrides fields and settings with the environment variables in [`CONFIG_FIELDS`] and [`SETTINGS`]
+    pub(super) fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.apply_env_from(env_value)
+    }
+
+    /// Like [`Config::apply_env`], with the variables looked up by `var`, which lets tests inject them
+    pub(crate) fn apply_env_from(
+        &mut self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> anyhow::Result<()> {
+        for field in CONFIG_FIELDS {
+            if let Some(value) = var(field.env) {
+                self.file_values.insert(field.key, self.value(field.key));
+                self.set_field(field.key, &value)
+                    .with_context(|| format!("Invalid value in {}", field.env))?;
+                self.overridden(field.key, Origin::Env(field.env));
+            }
+        }
+
+        for spec in SETTINGS {
+            if let Some(value) = var(spec.env) {
+                self.settings
+                    .set(spec.key, &value)
+                    .with_context(|| format!("Invalid value in {}", spec.env))?;
+                self.overridden(spec.key, Origin::Env(spec.env));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Overrides a setting with the value of a command line flag.
+    /// Goes through [`super::settings::Settings::set`], so paths are made absolute like any other value.
+    pub(super) fn apply_flag(
+        &mut self,
+        key: &'static str,
+        flag: &'static str,
+        value: &str,
+    ) -> anyhow::Result<()> {
+        self.settings.set(key, value)?;
+        self.overridden(key, Origin::Flag(flag));
+        Ok(())
+    }
+
+    /// Remembers where an overridden value comes from and what it was
+    fn overridden(&mut self, key: &'static str, origin: Origin) {
+        self.origins.insert(key, origin);
+        self.override_values.insert(key, self.value(key));
+    }
+
+    /// Whether a command changed an overridden value, it's stored then instead of the value from the file
+    pub(super) fn changed_override(&self, key: &str) -> bool {
+        self.override_values
+            .get(key)
+            .is_some_and(|value| *value != self.value(key))
+    }
+
+    /// The value of a profile field as [`Config::store`] writes it, i.e. without the overrides.
+    /// Stored tokens are looked up by these, so overriding the host or course doesn't lose them.
+    pub fn stored_value(&self, key: &str) -> String {
+        match self.file_values.get(key) {
+            Some(value) if !self.changed_override(key) => value.clone(),
+            _ => self.value(key),
+        }
+        .unwrap_or_default()
+    }
+
+    fn set_field(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match key {
+            "host" => self.host = normalize_host(value)?,
+            "user" => self.user = value.to_string(),
+            "course" => self.course = value.to_string(),
+            "api_flavor" => {
+                self.api_flavor =
+                    Some(ApiFlavor::from_str(value, true).map_err(anyhow::Error::msg)?)
+            }
+            "token" => self.token = value.trim().to_string(),
+            "credential_store" => {
+                self.credential_store =
+                    CredentialStoreKind::from_str(value, true).map_err(anyhow::Error::msg)?
+            }
+            "credential_helper" => self.credential_helper = Some(value.to_string()),
+            "password_command" => self.password_command = Some(value.to_string()),
+            "expiry_margin_seconds" => self.expiry_margin_seconds = value.parse()?,
+            _ => unreachable!("every key in CONFIG_FIELDS is handled"),
+        }
+        Ok(())
+    }
+
+    /// Takes back an overridden field from `stored`, so overrides never end up in the config file
+    pub(super) fn restore_field(&mut self, key: &str, stored: &Config) {
+        match key {
+            "host" => self.host = stored.host.clone(),
+            "user" => self.user = stored.user.clone(),
+            "course" => self.course = stored.course.clone(),
+            "api_flavor" => self.api_flavor = stored.api_flavor,
+            "token" => self.token = stored.token.clone(),
+            "credential_store" => self.credential_store = stored.credential_store,
+            "credential_helper" => self.credential_helper = stored.credential_helper.clone(),
+            "password_command" => self.password_command = stored.password_command.clone(),
+            "expiry_margin_seconds" => self.expiry_margin_seconds = stored.expiry_margin_seconds,
+            // settings are only written through `sbcli config`
+            _ => {}
+        }
+    }
+
+    /// The value of a profile field or setting as is, including the token
+    fn value(&self, key: &str) -> Option<String> {
+        match key {
+            "host" => Some(self.host.clone()),
+            "user" => Some(self.user.clone()),
+            "course" => Some(self.course.clone()),
+            "api_flavor" => self.api_flavor.map(value_name),
+            "token" => Some(self.token.clone()),
+            "credential_store" => Some(value_name(self.credential_store)),
+            "credential_helper" => self.credential_helper.clone(),
+            "password_command" => self.password_command.clone(),
+            "expiry_margin_seconds" => Some(self.expiry_margin_seconds.to_string()),
+            _ => self.settings.get(key).ok().flatten(),
+        }
+    }
+
+    /// The effective value of a profile field or setting, `None` if it's unset.
+    /// The token is never printed.
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let value = match key {
+            "token" => Some("(hidden)".to_string()).filter(|_| !self.token.is_empty()),
+            _ if CONFIG_FIELDS.iter().any(|field| field.key == key) => self.value(key),
+            _ if SETTINGS.iter().any(|spec| spec.key == key) => return self.settings.get(key),
+            _ => {
+                let keys = CONFIG_FIELDS
+                    .iter()
+                    .map(|field| field.key)
+                    .chain(SETTINGS.iter().map(|spec| spec.key));
+                let hint = match did_you_mean(key, keys) {
+                    Some(suggestion) => format!(" Did you mean `{}`?", suggestion),
+                    None => String::new(),
+                };
+                anyhow::bail!(
+                    "Unknown key `{}`.{} Run `{} config list` to see all keys.",
+                    key,
+                    hint,
+                    env!("CARGO_PKG_NAME")
+                );
+            }
+        };
+
+        Ok(value.filter(|v| !v.is_empty()))
+    }
+
+    pub fn origin(&self, key: &str) -> anyhow::Result<Origin> {
+        if let Some(origin) = self.origins.get(key) {
+            return Ok(*origin);
+        }
+
+        Ok(if self.get(key)? == Config::default().get(key)? {
+            Origin::Default
+        } else {
+            Origin::File
+        })
+    }
+
+    /// Whether the environment or a flag decides this value instead of the config file
+    pub fn is_overridden(&self, key: &str) -> bool {
+        self.origins.contains_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::{profile::DEFAULT_PROFILE, ConfigFile};
+
+    #[test]
+    fn test_env_overrides_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.ron");
+
+        let mut cfg = Config::from_file(&ConfigFile::load_path(&path).unwrap(), DEFAULT_PROFILE);
+        cfg.course = "ckurs".to_string();
+        cfg.store().unwrap();
+
+        let env = HashMap::from([
+            ("SBCLI_COURSE", "other"),
+            ("SBCLI_TOKEN", "from-env"),
+            ("SBCLI_CREDENTIAL_STORE", "encrypted_file"),
+        ]);
+        let apply_env = |cfg: &mut Config| {
+            cfg.apply_env_from(|var| env.get(var).map(|value| value.to_string()))
+                .unwrap()
+        };
+
+        let mut cfg = Config::from_file(&ConfigFile::load_path(&path).unwrap(), DEFAULT_PROFILE);
+        apply_env(&mut cfg);
+
+        assert_eq!(cfg.course, "other");
+        assert_eq!(cfg.credential_store, CredentialStoreKind::EncryptedFile);
+        // stored tokens are looked up by the values in the file
+        assert_eq!(cfg.stored_value("course"), "ckurs");
+        assert_eq!(cfg.origin("course").unwrap(), Origin::Env("SBCLI_COURSE"));
+        assert_eq!(cfg.origin("user").unwrap(), Origin::Default);
+
+        cfg.user = "bob".to_string();
+        cfg.store().unwrap();
+
+        let stored = Config::from_file(&ConfigFile::load_path(&path).unwrap(), DEFAULT_PROFILE);
+        assert_eq!(stored.course, "ckurs");
+        assert_eq!(stored.user, "bob");
+        assert_eq!(stored.origin("user").unwrap(), Origin::File);
+        assert_eq!(stored.token, "");
+
+        // values a command sets itself are stored, even if they're overridden
+        let mut cfg = Config::from_file(&ConfigFile::load_path(&path).unwrap(), DEFAULT_PROFILE);
+        apply_env(&mut cfg);
+        cfg.token = "issued".to_string();
+        cfg.store().unwrap();
+
+        let stored = Config::from_file(&ConfigFile::load_path(&path).unwrap(), DEFAULT_PROFILE);
+        assert_eq!(stored.token, "issued");
+        assert_eq!(stored.course, "ckurs");
+    }
+}
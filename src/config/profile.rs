@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::requests::ApiFlavor;
+
+/// The name of the profile used when none has been created explicitly
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Everything that belongs to one SmartBeans account in one course
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub host: String,
+    pub user: String,
+    pub course: String,
+    /// Detected from the first submissions fetched, e.g. during sync, guessed from the host until then
+    pub api_flavor: Option<ApiFlavor>,
+    /// Only used by the plaintext credential store, see [`crate::credentials`]
+    pub token: String,
+    /// Command that prints the password, e.g. from a password manager
+    pub password_command: Option<String>,
+    pub last_login_time: Option<DateTime<Utc>>,
+    /// When the session expires, if the server or the token told us
+    pub token_expiry: Option<DateTime<Utc>>,
+}
+
+/// Profile names end up in file and directory names, so they're limited to letters, digits, `-` and `_`
+pub fn check_name(name: &str) -> anyhow::Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        anyhow::bail!(
+            "`{}` is not a valid profile name, use only letters, digits, `-` and `_`",
+            name
+        );
+    }
+    Ok(())
+}
+
+impl Profile {
+    pub fn is_configured(&self) -> bool {
+        !(self.user.is_empty() || self.course.is_empty() || self.host.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("uni-2_cs").is_ok());
+        assert!(check_name(DEFAULT_PROFILE).is_ok());
+
+        for name in ["", "../../x", "a/b", "a\\b", ".", "kurs ws"] {
+            assert!(check_name(name).is_err(), "{:?} should be rejected", name);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    versioning::{self, Versioned},
+    Context, Locked,
+};
+
+/// Version of the queue file format, see [`versioning`]
+pub const QUEUE_VERSION: u32 = 1;
+
+/// A submission that couldn't be sent because SmartBeans wasn't reachable.
+/// The file is snapshotted, so later edits don't change what gets submitted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedSubmission {
+    pub id: usize,
+    pub task_id: usize,
+    /// Where the content was read from, only for showing it
+    pub path: PathBuf,
+    pub content: String,
+    pub queued_at: DateTime<Utc>,
+}
+
+/// Submissions waiting to be sent, oldest first, managed with `sbcli queue`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubmissionQueue {
+    /// Read by [`versioning::load`] before the rest of the file
+    #[serde(skip_deserializing, default = "queue_version")]
+    version: u32,
+    items: Vec<QueuedSubmission>,
+    /// Never reused, so an ID shown by `sbcli queue list` always means the same submission
+    next_id: usize,
+}
+
+impl Default for SubmissionQueue {
+    fn default() -> Self {
+        Self {
+            version: QUEUE_VERSION,
+            items: Vec::new(),
+            next_id: 1,
+        }
+    }
+}
+
+fn queue_version() -> u32 {
+    QUEUE_VERSION
+}
+
+impl Versioned for SubmissionQueue {
+    const VERSION: u32 = QUEUE_VERSION;
+    const NAME: &'static str = "queue";
+}
+
+impl SubmissionQueue {
+    /// Loads the queue of the current profile for reading
+    pub fn load(ctx: &Context) -> anyhow::Result<Self> {
+        versioning::load(&ctx.queue_path(&ctx.profile()?))
+    }
+
+    /// Loads the queue of the current profile for changing it, see [`Locked`]
+    pub fn lock(ctx: &Context) -> anyhow::Result<Locked<Self>> {
+        versioning::lock(&ctx.queue_path(&ctx.profile()?))
+    }
+
+    /// Deletes the queue file of a profile and returns how many submissions were never sent
+    pub fn delete(ctx: &Context, profile: &str) -> anyhow::Result<usize> {
+        let path = ctx.queue_path(profile);
+        if !path.exists() {
+            return Ok(0);
+        }
+
+        let queue = versioning::lock::<Self>(&path)?;
+        std::fs::remove_file(&path)?;
+        Ok(queue.items.len())
+    }
+
+    pub fn items(&self) -> &[QueuedSubmission] {
+        &self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Adds a submission to the end of the queue and returns its ID
+    pub fn push(
+        &mut self,
+        task_id: usize,
+        path: &Path,
+        content: String,
+        queued_at: DateTime<Utc>,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.items.push(QueuedSubmission {
+            id,
+            task_id,
+            path: path.to_path_buf(),
+            content,
+            queued_at,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<QueuedSubmission> {
+        let index = self.items.iter().position(|item| item.id == id)?;
+        Some(self.items.remove(index))
+    }
+
+    /// Removes all submissions and returns how many there were
+    pub fn clear(&mut self) -> usize {
+        std::mem::take(&mut self.items).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_ids() {
+        let mut queue = SubmissionQueue::default();
+        let now = Utc::now();
+
+        let first = queue.push(1, Path::new("1.c"), "int main(){}".to_string(), now);
+        let second = queue.push(2, Path::new("2.c"), String::new(), now);
+        assert_eq!((first, second), (1, 2));
+
+        assert_eq!(queue.remove(first).unwrap().task_id, 1);
+        assert!(queue.remove(first).is_none());
+
+        // IDs of removed submissions are not handed out again
+        assert_eq!(queue.push(3, Path::new("3.c"), String::new(), now), 3);
+        assert_eq!(queue.clear(), 2);
+        assert!(queue.is_empty());
+    }
+}
@@ -1,5 +1,277 @@
+use std::{fmt::Display, path::PathBuf, sync::OnceLock};
+
+use clap::ValueEnum;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use crate::util::did_you_mean;
+
+use super::WORKSPACE_ENV_VAR;
+
+pub const DEFAULT_SYNC_CONCURRENCY: usize = 8;
+
+/// Applied once per process, like the color mode
+static PROMPT_ANSWER: OnceLock<PromptAnswer> = OnceLock::new();
+
 /// User settings for the application.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Settings {}
+/// They apply to all profiles and are managed with `sbcli config get|set|unset|list`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub editor: Option<String>,
+    pub workspace_root: Option<PathBuf>,
+    pub color: ColorMode,
+    pub prompt_answer: PromptAnswer,
+    pub sync_concurrency: usize,
+    pub output_format: OutputFormat,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            editor: None,
+            workspace_root: None,
+            color: ColorMode::default(),
+            prompt_answer: PromptAnswer::default(),
+            sync_concurrency: DEFAULT_SYNC_CONCURRENCY,
+            output_format: OutputFormat::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMode {
+    /// Colored output if the terminal supports it
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn apply(self) {
+        match self {
+            ColorMode::Auto => colored::control::unset_override(),
+            ColorMode::Always => colored::control::set_override(true),
+            ColorMode::Never => colored::control::set_override(false),
+        }
+    }
+}
+
+/// How yes/no questions are answered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptAnswer {
+    #[default]
+    Ask,
+    Yes,
+    No,
+}
+
+impl PromptAnswer {
+    pub fn apply(self) {
+        let _ = PROMPT_ANSWER.set(self);
+    }
+
+    pub fn current() -> Self {
+        PROMPT_ANSWER.get().copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Describes a setting for `sbcli config list` and error messages
+pub struct SettingSpec {
+    pub key: &'static str,
+    /// Environment variable that overrides the setting
+    pub env: &'static str,
+    pub description: &'static str,
+    /// The accepted values, for enums these are checked by [`ValueEnum`]
+    pub values: &'static str,
+}
+
+pub const SETTINGS: &[SettingSpec] = &[
+    SettingSpec {
+        key: "editor",
+        env: "SBCLI_EDITOR",
+        description: "Command to open tasks with, e.g. `code`. Uses the system default if unset",
+        values: "<command>",
+    },
+    SettingSpec {
+        key: "workspace_root",
+        env: WORKSPACE_ENV_VAR,
+        description:
+            "Directory the `tasks` folder is created in, defaults to `sbcli` in your home directory",
+        values: "<path>",
+    },
+    SettingSpec {
+        key: "color",
+        env: "SBCLI_COLOR",
+        description: "When to use colored output",
+        values: "auto|always|never",
+    },
+    SettingSpec {
+        key: "prompt_answer",
+        env: "SBCLI_PROMPT_ANSWER",
+        description: "Answer yes/no questions automatically instead of asking",
+        values: "ask|yes|no",
+    },
+    SettingSpec {
+        key: "sync_concurrency",
+        env: "SBCLI_SYNC_CONCURRENCY",
+        description: "How many tasks are synced at the same time",
+        values: "<number>",
+    },
+    SettingSpec {
+        key: "output_format",
+        env: "SBCLI_OUTPUT_FORMAT",
+        description: "Output format of listing commands",
+        values: "text|json",
+    },
+];
+
+impl Settings {
+    /// Looks up the description of a setting, failing with a suggestion for unknown keys
+    pub fn spec(key: &str) -> anyhow::Result<&'static SettingSpec> {
+        SETTINGS.iter().find(|spec| spec.key == key).ok_or_else(|| {
+            let hint = match did_you_mean(key, SETTINGS.iter().map(|spec| spec.key)) {
+                Some(suggestion) => format!(" Did you mean `{}`?", suggestion),
+                None => String::new(),
+            };
+            anyhow::anyhow!(
+                "Unknown setting `{}`.{} Run `{} config list` to see all settings.",
+                key,
+                hint,
+                env!("CARGO_PKG_NAME")
+            )
+        })
+    }
+
+    /// The current value of a setting, `None` if it's unset and has no default
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Self::spec(key)?;
+
+        Ok(match key {
+            "editor" => self.editor.clone(),
+            "workspace_root" => self
+                .workspace_root
+                .as_ref()
+                .map(|p| p.display().to_string()),
+            "color" => Some(value_name(self.color)),
+            "prompt_answer" => Some(value_name(self.prompt_answer)),
+            "sync_concurrency" => Some(self.sync_concurrency.to_string()),
+            "output_format" => Some(value_name(self.output_format)),
+            _ => unreachable!("every key in SETTINGS is handled"),
+        })
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let spec = Self::spec(key)?;
+        let invalid = |reason: &dyn Display| {
+            anyhow::anyhow!(
+                "Invalid value `{}` for `{}` (expected {}): {}",
+                value,
+                key,
+                spec.values,
+                reason
+            )
+        };
+
+        match key {
+            "editor" if value.trim().is_empty() => return Err(invalid(&"must not be empty")),
+            "editor" => self.editor = Some(value.to_string()),
+            "workspace_root" if value.trim().is_empty() => {
+                return Err(invalid(&"must not be empty"))
+            }
+            // relative to where it was set, not to wherever the next command runs
+            "workspace_root" => {
+                self.workspace_root = Some(std::path::absolute(value).map_err(|e| invalid(&e))?)
+            }
+            "color" => self.color = ColorMode::from_str(value, true).map_err(|e| invalid(&e))?,
+            "prompt_answer" => {
+                self.prompt_answer = PromptAnswer::from_str(value, true).map_err(|e| invalid(&e))?
+            }
+            "sync_concurrency" => {
+                self.sync_concurrency = match value.parse::<usize>() {
+                    Ok(0) => return Err(invalid(&"must be at least 1")),
+                    Ok(n) => n,
+                    Err(e) => return Err(invalid(&e)),
+                }
+            }
+            "output_format" => {
+                self.output_format = OutputFormat::from_str(value, true).map_err(|e| invalid(&e))?
+            }
+            _ => unreachable!("every key in SETTINGS is handled"),
+        }
+
+        Ok(())
+    }
+
+    /// Resets a setting to its default
+    pub fn unset(&mut self, key: &str) -> anyhow::Result<()> {
+        Self::spec(key)?;
+        let default = Settings::default();
+
+        match key {
+            "editor" => self.editor = default.editor,
+            "workspace_root" => self.workspace_root = default.workspace_root,
+            "color" => self.color = default.color,
+            "prompt_answer" => self.prompt_answer = default.prompt_answer,
+            "sync_concurrency" => self.sync_concurrency = default.sync_concurrency,
+            "output_format" => self.output_format = default.output_format,
+            _ => unreachable!("every key in SETTINGS is handled"),
+        }
+
+        Ok(())
+    }
+}
+
+/// All values of an enum as they're accepted on the command line, e.g. `auto|always|never`
+pub fn possible_values<T: ValueEnum>() -> String {
+    T::value_variants()
+        .iter()
+        .map(|value| value_name(value.clone()))
+        .join("|")
+}
+
+pub fn value_name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set_unset() {
+        let mut settings = Settings::default();
+
+        for spec in SETTINGS {
+            settings.get(spec.key).unwrap();
+        }
+
+        settings.set("color", "Never").unwrap();
+        assert_eq!(settings.get("color").unwrap().as_deref(), Some("never"));
+        settings.unset("color").unwrap();
+        assert_eq!(settings.color, ColorMode::Auto);
+
+        settings.set("workspace_root", "rel").unwrap();
+        assert!(settings.workspace_root.as_ref().unwrap().is_absolute());
+
+        assert!(settings.set("sync_concurrency", "0").is_err());
+        assert!(settings.set("output_format", "yaml").is_err());
+
+        let err = settings.get("edtor").unwrap_err().to_string();
+        assert!(err.contains("Did you mean `editor`?"), "{}", err);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    fs::File,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use fs4::FileExt;
+
+use crate::util::{sibling_path, write_atomic};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+
+/// A file format that carries a version and knows how to upgrade older versions of itself.
+///
+/// Migrations work on the file contents, one version at a time, so each step only has to
+/// know the layout it starts from. Steps that need the old layout should deserialize it into
+/// their own struct instead of the current one, which keeps changing.
+pub trait Versioned: Serialize + DeserializeOwned + Default {
+    /// The version this sbcli writes
+    const VERSION: u32;
+    /// Name of the file in messages, e.g. "config"
+    const NAME: &'static str;
+
+    /// The version of a file written before the format carried one
+    fn legacy_version(_contents: &str) -> anyhow::Result<u32> {
+        Ok(1)
+    }
+
+    /// Upgrades the contents of a file from `version` to `version + 1`
+    fn migrate(version: u32, _contents: &str) -> anyhow::Result<String> {
+        anyhow::bail!(
+            "Don't know how to upgrade the {} file from version {}",
+            Self::NAME,
+            version
+        )
+    }
+}
+
+/// Only the version of a file, everything else is ignored.
+/// Before versioning, the config stored the sbcli release in the same field.
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    version: FileVersion,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileVersion {
+    Format(u32),
+    /// A release like "0.1.4", or no version at all
+    Unversioned(IgnoredAny),
+}
+
+impl Default for FileVersion {
+    fn default() -> Self {
+        FileVersion::Unversioned(IgnoredAny)
+    }
+}
+
+/// Loads a versioned file, upgrading it if it was written by an older sbcli.
+/// The previous file is kept next to it as a backup. A missing file is created with defaults.
+pub fn load<T: Versioned>(path: &Path) -> anyhow::Result<T> {
+    if !path.exists() {
+        let value = T::default();
+        store(path, &value)?;
+        return Ok(value);
+    }
+
+    let mut contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the {} file {}", T::NAME, path.display()))?;
+    let header: Header = ron::from_str(&contents)
+        .with_context(|| format!("The {} file {} is malformed", T::NAME, path.display()))?;
+
+    let mut version = match header.version {
+        FileVersion::Format(version) => version,
+        FileVersion::Unversioned(_) => T::legacy_version(&contents)?,
+    };
+
+    if version > T::VERSION {
+        anyhow::bail!(
+            "The {} file {} was written by a newer sbcli (format version {}, this sbcli supports up to {}). Please update sbcli.",
+            T::NAME,
+            path.display(),
+            version,
+            T::VERSION
+        );
+    }
+
+    if version == T::VERSION {
+        return ron::from_str(&contents)
+            .with_context(|| format!("The {} file {} is malformed", T::NAME, path.display()));
+    }
+
+    let backup = backup_path(path, version);
+    std::fs::copy(path, &backup)
+        .with_context(|| format!("Failed to back up the {} file before upgrading it", T::NAME))?;
+
+    while version < T::VERSION {
+        contents = T::migrate(version, &contents).with_context(|| {
+            format!(
+                "Failed to upgrade the {} file from version {}, the original is kept at {}",
+                T::NAME,
+                version,
+                backup.display()
+            )
+        })?;
+        version += 1;
+    }
+
+    let value: T = ron::from_str(&contents)?;
+    store(path, &value)?;
+    eprintln!(
+        "Upgraded the {} file to version {}, the previous one was saved as {}",
+        T::NAME,
+        T::VERSION,
+        backup.display()
+    );
+
+    Ok(value)
+}
+
+/// Writes the file atomically: readers see either the old or the new contents, never a partial write.
+/// Callers that modify what they loaded should hold a [`Locked`] instead, so no update gets lost.
+pub fn store<T: Versioned>(path: &Path, value: &T) -> anyhow::Result<()> {
+    write_atomic(path, to_ron(value)?.as_bytes())
+        .with_context(|| format!("Failed to write the {} file {}", T::NAME, path.display()))
+}
+
+/// A versioned file loaded while holding an exclusive lock on it.
+/// Other sbcli processes that want to change the file wait until this is dropped,
+/// so a load-modify-store cycle can't interleave with theirs.
+pub struct Locked<T> {
+    value: T,
+    path: PathBuf,
+    _lock: File,
+}
+
+impl<T: Versioned> Locked<T> {
+    /// Writes the value back and releases the lock
+    pub fn store(self) -> anyhow::Result<T> {
+        store(&self.path, &self.value)?;
+        Ok(self.value)
+    }
+}
+
+impl<T> Deref for Locked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Locked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// Locks the file and loads it, waiting for other processes to release it first
+pub fn lock<T: Versioned>(path: &Path) -> anyhow::Result<Locked<T>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // the file itself gets replaced on every store, so the lock lives in a separate one
+    let lock_path = sibling_path(path, ".lock");
+    let lock = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open the lock file {}", lock_path.display()))?;
+    lock.lock_exclusive()
+        .with_context(|| format!("Failed to lock the {} file {}", T::NAME, path.display()))?;
+
+    Ok(Locked {
+        value: load(path)?,
+        path: path.to_path_buf(),
+        _lock: lock,
+    })
+}
+
+/// e.g. `config.ron` -> `config.ron.v1.bak`
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+    sibling_path(path, &format!(".v{}.bak", version))
+}
+
+pub fn to_ron<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(ron::ser::to_string_pretty(
+        value,
+        ron::ser::PrettyConfig::default(),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{profile::DEFAULT_PROFILE, ConfigFile, CONFIG_VERSION};
+
+    #[test]
+    fn test_upgrade_legacy_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.ron");
+
+        std::fs::write(
+            &path,
+            r#"(version: "0.1.4", host: "https://sb.example", user: "alice", course: "ckurs", token: "tok", last_login_time: None)"#,
+        )
+        .unwrap();
+
+        let file = load::<ConfigFile>(&path).unwrap();
+        let profile = &file.profiles[DEFAULT_PROFILE];
+        assert_eq!(profile.host, "https://sb.example");
+        assert_eq!(profile.token, "tok");
+        assert!(backup_path(&path, 1).exists());
+
+        let reloaded = load::<ConfigFile>(&path).unwrap();
+        assert_eq!(reloaded.version, CONFIG_VERSION);
+        assert_eq!(reloaded.profiles[DEFAULT_PROFILE].user, "alice");
+
+        std::fs::write(&path, format!("(version: {})", CONFIG_VERSION + 1)).unwrap();
+        let err = load::<ConfigFile>(&path).unwrap_err().to_string();
+        assert!(err.contains("newer sbcli"), "{}", err);
+    }
+
+    #[test]
+    fn test_locked_updates_are_not_lost() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.ron");
+
+        let threads = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        let mut file = lock::<ConfigFile>(&path).unwrap();
+                        file.expiry_margin_seconds += 1;
+                        file.store().unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let initial = ConfigFile::default().expiry_margin_seconds;
+        let file = load::<ConfigFile>(&path).unwrap();
+        assert_eq!(file.expiry_margin_seconds, initial + 80);
+    }
+}
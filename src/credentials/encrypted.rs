@@ -0,0 +1,182 @@
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, util::is_interactive};
+
+use super::{account_key, CredentialStore};
+
+/// Environment variable to provide the passphrase without a prompt
+pub const PASSPHRASE_ENV_VAR: &str = "SBCLI_PASSPHRASE";
+const FILE_NAME: &str = "credentials.enc";
+
+/// Asking once per process is enough
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Keeps tokens in a file next to the config, encrypted with a key derived from a passphrase.
+/// The file holds the tokens of all accounts, keyed by [`account_key`].
+pub struct EncryptedFile {
+    path: PathBuf,
+}
+
+/// On-disk layout, all fields base64 encoded
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl EncryptedFile {
+    /// The credentials are kept next to the config file
+    pub fn new(config_path: &Path) -> Self {
+        Self {
+            path: config_path.with_file_name(FILE_NAME),
+        }
+    }
+
+    fn passphrase(&self) -> anyhow::Result<&'static str> {
+        if let Some(passphrase) = PASSPHRASE.get() {
+            return Ok(passphrase);
+        }
+
+        let passphrase = match std::env::var(PASSPHRASE_ENV_VAR) {
+            Ok(passphrase) => passphrase,
+            Err(_) if is_interactive() => {
+                if self.path.exists() {
+                    rpassword::prompt_password("Passphrase for your stored credentials: ")?
+                } else {
+                    let passphrase = rpassword::prompt_password(
+                        "Choose a passphrase to encrypt your credentials: ",
+                    )?;
+                    if rpassword::prompt_password("Repeat the passphrase: ")? != passphrase {
+                        anyhow::bail!("The passphrases don't match");
+                    }
+                    passphrase
+                }
+            }
+            Err(_) => anyhow::bail!(
+                "Your credentials are encrypted, but there is no terminal to ask for the passphrase. Set {} instead.",
+                PASSPHRASE_ENV_VAR
+            ),
+        };
+
+        Ok(PASSPHRASE.get_or_init(|| passphrase))
+    }
+
+    fn read(&self) -> anyhow::Result<HashMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let envelope: Envelope = serde_json::from_slice(&std::fs::read(&self.path)?)
+            .with_context(|| format!("{} is corrupted", self.path.display()))?;
+        let plaintext = decrypt(&envelope, self.passphrase()?)?;
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn write(&self, tokens: &HashMap<String, String>) -> anyhow::Result<()> {
+        let envelope = encrypt(&serde_json::to_vec(tokens)?, self.passphrase()?)?;
+
+        // only readable by the user, the machines are often shared
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&self.path)?;
+        // `mode` only applies to new files, older versions created it readable by everyone
+        #[cfg(unix)]
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(&serde_json::to_vec_pretty(&envelope)?)?;
+        Ok(())
+    }
+}
+
+impl CredentialStore for EncryptedFile {
+    fn get(&self, cfg: &Config) -> anyhow::Result<Option<String>> {
+        Ok(self.read()?.remove(&account_key(cfg)))
+    }
+
+    fn store(&self, cfg: &mut Config, token: &str) -> anyhow::Result<()> {
+        let mut tokens = self.read()?;
+        tokens.insert(account_key(cfg), token.to_string());
+        self.write(&tokens)
+    }
+
+    fn erase(&self, cfg: &mut Config) -> anyhow::Result<()> {
+        let mut tokens = self.read()?;
+        if tokens.remove(&account_key(cfg)).is_some() {
+            self.write(&tokens)?;
+        }
+        Ok(())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> anyhow::Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive the encryption key: {}", e))?;
+    Ok(key)
+}
+
+fn encrypt(plaintext: &[u8], passphrase: &str) -> anyhow::Result<Envelope> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt credentials"))?;
+
+    Ok(Envelope {
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    })
+}
+
+fn decrypt(envelope: &Envelope, passphrase: &str) -> anyhow::Result<Vec<u8>> {
+    let salt = STANDARD.decode(&envelope.salt)?;
+    let nonce = STANDARD.decode(&envelope.nonce)?;
+    let ciphertext = STANDARD.decode(&envelope.ciphertext)?;
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase for the stored credentials"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let envelope = encrypt(b"secret token", "correct horse").unwrap();
+
+        assert_eq!(
+            decrypt(&envelope, "correct horse").unwrap(),
+            b"secret token"
+        );
+        assert!(decrypt(&envelope, "battery staple").is_err());
+    }
+}
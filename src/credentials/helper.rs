@@ -0,0 +1,87 @@
+use std::{io::Write, process::Stdio};
+
+use crate::{config::Config, util::shell_command};
+
+use super::{account, CredentialStore};
+
+/// Delegates storage to an external command, speaking git's credential helper protocol.
+///
+/// The command is invoked with `get`, `store` or `erase` appended and receives the account
+/// as `key=value` lines on stdin. On `get` it answers with a `password=<token>` line.
+/// This means existing git helpers, e.g. `git credential-libsecret`, work out of the box.
+pub struct Helper {
+    command: String,
+}
+
+impl Helper {
+    pub fn new(command: String) -> Self {
+        Self { command }
+    }
+
+    fn run(&self, action: &str, input: &str) -> anyhow::Result<String> {
+        let mut child = shell_command(&format!("{} {}", self.command, action))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to run credential helper `{}`: {}", self.command, e)
+            })?;
+
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(input.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Credential helper `{} {}` failed with {}",
+                self.command,
+                action,
+                output.status
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Describes the account in git's credential format
+fn describe(cfg: &Config) -> String {
+    let (host, user, course) = account(cfg);
+    let (protocol, host) = match reqwest::Url::parse(&host) {
+        Ok(url) => (
+            url.scheme().to_string(),
+            url.host_str().unwrap_or_default().to_string(),
+        ),
+        Err(_) => ("https".to_string(), host),
+    };
+
+    format!(
+        "protocol={}\nhost={}\nusername={}\npath={}\n",
+        protocol, host, user, course
+    )
+}
+
+impl CredentialStore for Helper {
+    fn get(&self, cfg: &Config) -> anyhow::Result<Option<String>> {
+        let output = self.run("get", &format!("{}\n", describe(cfg)))?;
+
+        Ok(output
+            .lines()
+            .find_map(|line| line.strip_prefix("password="))
+            .map(str::to_string)
+            .filter(|token| !token.is_empty()))
+    }
+
+    fn store(&self, cfg: &mut Config, token: &str) -> anyhow::Result<()> {
+        self.run("store", &format!("{}password={}\n\n", describe(cfg), token))?;
+        Ok(())
+    }
+
+    fn erase(&self, cfg: &mut Config) -> anyhow::Result<()> {
+        self.run("erase", &format!("{}\n", describe(cfg)))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+mod encrypted;
+mod helper;
+mod plaintext;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+use self::{encrypted::EncryptedFile, helper::Helper, plaintext::Plaintext};
+
+/// Where session tokens are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum CredentialStoreKind {
+    /// In the config file, next to host and username
+    #[default]
+    Plaintext,
+    /// In a separate file, encrypted with a passphrase
+    #[value(alias = "encrypted-file")]
+    EncryptedFile,
+    /// Delegated to an external command, like git's credential helpers
+    Helper,
+}
+
+/// A backend for session tokens.
+///
+/// `cfg` describes the account the token belongs to. Backends may modify it,
+/// persisting those changes is up to the caller.
+pub trait CredentialStore {
+    fn get(&self, cfg: &Config) -> anyhow::Result<Option<String>>;
+    fn store(&self, cfg: &mut Config, token: &str) -> anyhow::Result<()>;
+    fn erase(&self, cfg: &mut Config) -> anyhow::Result<()>;
+}
+
+/// Returns the backend selected in `cfg`
+pub fn backend(cfg: &Config) -> anyhow::Result<Box<dyn CredentialStore>> {
+    Ok(match cfg.credential_store {
+        CredentialStoreKind::Plaintext => Box::new(Plaintext),
+        CredentialStoreKind::EncryptedFile => Box::new(EncryptedFile::new(&cfg.path)),
+        CredentialStoreKind::Helper => {
+            let command = cfg.credential_helper.clone().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The credential helper backend requires a helper command to be configured"
+                )
+            })?;
+            Box::new(Helper::new(command))
+        }
+    })
+}
+
+/// Loads the session token for the account in `cfg`, or an empty string if there is none.
+///
+/// A token from the environment wins over any stored one.
+/// A plaintext token left over from before another backend was selected is moved into that backend.
+pub fn load_token(cfg: &mut Config) -> anyhow::Result<String> {
+    // a token from the environment is used as is and never stored
+    if cfg.is_overridden("token") {
+        return Ok(cfg.token.clone());
+    }
+
+    if cfg.credential_store != CredentialStoreKind::Plaintext && !cfg.token.is_empty() {
+        let token = std::mem::take(&mut cfg.token);
+        backend(cfg)?.store(cfg, &token)?;
+        cfg.store()?;
+        println!("Moved your session token out of the plaintext config.");
+    }
+
+    Ok(backend(cfg)?.get(cfg)?.unwrap_or_default())
+}
+
+/// Stores the session token for the account in `cfg`. The caller has to persist `cfg` afterwards.
+pub fn store_token(cfg: &mut Config, token: &str) -> anyhow::Result<()> {
+    backend(cfg)?.store(cfg, token)
+}
+
+/// Moves the current token to another backend and persists `cfg`
+pub fn switch_backend(
+    cfg: &mut Config,
+    kind: CredentialStoreKind,
+    helper: Option<String>,
+) -> anyhow::Result<()> {
+    let token = load_token(cfg)?;
+    if !token.is_empty() {
+        backend(cfg)?.erase(cfg)?;
+    }
+
+    cfg.credential_store = kind;
+    if helper.is_some() {
+        cfg.credential_helper = helper;
+    }
+
+    if !token.is_empty() {
+        store_token(cfg, &token)?;
+    }
+
+    cfg.store()
+}
+
+/// Takes the token of the account in `cfg` out of its backend, so it can be stored again with [`store_token`]
+/// once the host, user or course of the account changed. Plaintext tokens are part of the profile and stay where they are.
+pub fn take_token(cfg: &mut Config) -> anyhow::Result<Option<String>> {
+    if cfg.credential_store == CredentialStoreKind::Plaintext {
+        return Ok(None);
+    }
+
+    let backend = backend(cfg)?;
+    let token = backend.get(cfg)?;
+    if token.is_some() {
+        backend.erase(cfg)?;
+    }
+    Ok(token)
+}
+
+/// The host, user and course a token is stored under, as they're kept in the config file
+fn account(cfg: &Config) -> (String, String, String) {
+    (
+        cfg.stored_value("host"),
+        cfg.stored_value("user"),
+        cfg.stored_value("course"),
+    )
+}
+
+/// Identifies the profile a token belongs to in shared backends
+fn account_key(cfg: &Config) -> String {
+    let (host, user, course) = account(cfg);
+    format!("{}:{}@{}/{}", cfg.profile, user, host, course)
+}
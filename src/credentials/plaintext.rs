@@ -0,0 +1,22 @@
+use crate::config::Config;
+
+use super::CredentialStore;
+
+/// Keeps the token in the config file itself, which is how sbcli always did it
+pub struct Plaintext;
+
+impl CredentialStore for Plaintext {
+    fn get(&self, cfg: &Config) -> anyhow::Result<Option<String>> {
+        Ok(Some(cfg.token.clone()).filter(|token| !token.is_empty()))
+    }
+
+    fn store(&self, cfg: &mut Config, token: &str) -> anyhow::Result<()> {
+        cfg.token = token.to_string();
+        Ok(())
+    }
+
+    fn erase(&self, cfg: &mut Config) -> anyhow::Result<()> {
+        cfg.token.clear();
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 mod auth;
+mod cache;
 mod commands;
 mod config;
+mod credentials;
 mod requests;
 mod tasks;
 mod util;
@@ -9,19 +11,39 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
-use commands::{list_tasks, start_task, submit_task, sync};
-use config::Config;
+use commands::{
+    graph, info, list_tasks, queue_drop, queue_flush, queue_list, start_task, submit_task, sync,
+};
+use config::{Context, CONFIG_ENV_VAR};
+use credentials::CredentialStoreKind;
 
 use crate::commands::configure;
-use crate::commands::login;
-use crate::requests::ApiClient;
+use crate::commands::{
+    config_get, config_list, config_set, config_unset, login, login_status, logout, profile_add,
+    profile_list, profile_remove, profile_use,
+};
+use crate::requests::{ApiClient, ApiFlavor};
+use crate::tasks::graph::GraphFormat;
 
 #[derive(Debug, Parser)]
 struct Cli {
-    /// Use a custom config
-    #[arg(short, long)]
+    /// Use another config file for this invocation. Meta data is kept next to it
+    // no short flag, it's global and `-c` is taken by `--course`
+    #[arg(long, global = true, env = CONFIG_ENV_VAR)]
     config: Option<PathBuf>,
 
+    /// Use the given profile instead of the active one
+    #[arg(short, long, global = true)]
+    profile: Option<String>,
+
+    /// Directory the `tasks` folder is created in, overrides SBCLI_EXERCISE_DIR and the `workspace_root` setting
+    #[arg(long, global = true)]
+    workspace: Option<PathBuf>,
+
+    /// Don't connect to SmartBeans, work with the data cached by earlier commands instead
+    #[arg(long, global = true)]
+    offline: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -30,20 +52,66 @@ struct Cli {
 enum Commands {
     #[cfg(debug_assertions)]
     Dbg,
-    /// Configure the CLI
+    /// Configure the CLI. Without arguments, you'll be guided through the setup
     Configure {
         #[arg(short, long)]
-        username: String,
-        /// The course name, e.g. "ckurs"
+        username: Option<String>,
+        /// The course name, e.g. "ckurs". If omitted, you can pick one of the courses available to you
         #[arg(short, long)]
-        course: String,
+        course: Option<String>,
         /// The host of the SmartBeans instance, e.g. https://c109-223.cloud.gwdg.de
         #[arg(long)]
-        host: String,
+        host: Option<String>,
+        /// Which SmartBeans API the host serves. Detected automatically if omitted
+        #[arg(long, value_enum)]
+        api_flavor: Option<ApiFlavor>,
+        /// Where to keep your session token
+        #[arg(long, value_enum)]
+        credential_store: Option<CredentialStoreKind>,
+        /// Command of a git-style credential helper, implies `--credential-store helper`
+        #[arg(long)]
+        credential_helper: Option<String>,
+        /// Command that prints your password, used to log in without a prompt.
+        /// Pass an empty string to remove it
+        #[arg(long)]
+        password_command: Option<String>,
     },
-    // TODO: 1. token login 2. select course from list of options
     /// Login to SmartBeans
-    Login,
+    Login {
+        /// Login with a session token generated in the SmartBeans web interface.
+        /// Falls back to the SBCLI_TOKEN environment variable, and to a password prompt if neither is set
+        #[arg(long, conflicts_with = "token_stdin")]
+        token: Option<String>,
+        /// Read the session token from stdin
+        #[arg(long)]
+        token_stdin: bool,
+        /// Read the password from stdin instead of prompting for it.
+        /// Without this, the SBCLI_PASSWORD environment variable and the configured password command are tried first
+        #[arg(long, conflicts_with_all = ["token", "token_stdin"])]
+        password_stdin: bool,
+        /// Login in your browser, e.g. for OAuth/LTI accounts
+        #[arg(long, conflicts_with_all = ["token", "token_stdin", "password_stdin"])]
+        browser: bool,
+        /// Show who you're logged in as and when the session expires
+        #[arg(long, conflicts_with_all = ["token", "token_stdin", "password_stdin", "browser"])]
+        status: bool,
+    },
+    /// Logout and remove the stored session
+    Logout {
+        /// Also remove cached task data and synced submissions for this account
+        #[arg(long)]
+        all: bool,
+    },
+    /// Read and change settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Manage profiles for different hosts, courses and accounts
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
     /// Get the tasks for the current course and save them locally
     Sync {
         /// Force sync even if the exercises directory is not empty
@@ -56,31 +124,102 @@ enum Commands {
     },
     /// List all tasks and their current status
     List,
+    /// Show a task with its prerequisites and your activity on it
+    Info {
+        /// ID or short name of the task
+        task: String,
+    },
+    /// Print the tasks and their prerequisites as a graph, grouped by tag and colored by progress
+    Graph {
+        /// Defaults to json if the `output_format` setting is json, dot otherwise
+        #[arg(short, long, value_enum)]
+        format: Option<GraphFormat>,
+    },
     #[cfg(debug_assertions)] // TODO: implement
     /// Show your progress
     Progress,
     /// Work on the next task, or the task with the given ID
-    Start {
-        task_id: Option<usize>,
-    },
+    Start { task_id: Option<usize> },
     /// Submit an exercise to SmartBeans
     Submit {
         path: PathBuf,
+        /// ID or short name of the task, needed for files outside of the workspace
+        #[arg(short, long)]
+        task: Option<String>,
     },
     /// Run the tests for a local exercise
-    Test {
-        path: PathBuf,
+    Test { path: PathBuf },
+    /// Manage submissions that were queued while SmartBeans couldn't be reached
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommands,
     },
 }
 
+#[derive(Debug, Subcommand)]
+enum QueueCommands {
+    /// List the queued submissions, oldest first
+    List,
+    /// Send the queued submissions now
+    Flush,
+    /// Remove a queued submission without sending it
+    Drop {
+        /// The ID shown by `sbcli queue list`
+        #[arg(conflicts_with = "all")]
+        id: Option<usize>,
+        /// Remove all queued submissions
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// Print the effective value of a profile field or setting
+    Get { key: String },
+    /// Change a setting
+    Set { key: String, value: String },
+    /// Reset a setting to its default
+    Unset { key: String },
+    /// List the profile and all settings with their current values
+    List {
+        /// Show where each value comes from: default, config file, environment or flag
+        #[arg(long)]
+        show_origin: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ProfileCommands {
+    /// List all profiles, the current one is marked with `*`
+    List,
+    /// Add a new profile
+    Add {
+        name: String,
+        #[arg(short, long)]
+        username: String,
+        /// The course name, e.g. "ckurs". If omitted, you can pick one of the courses available to you
+        #[arg(short, long)]
+        course: Option<String>,
+        /// The host of the SmartBeans instance, e.g. https://c109-223.cloud.gwdg.de
+        #[arg(long)]
+        host: String,
+    },
+    /// Make a profile the active one
+    Use { name: String },
+    /// Remove a profile and its session. Synced tasks are kept
+    Remove { name: String },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    // Using custom config
-    if let Some(config_path) = cli.config {
-        let config: Config = confy::load_path(config_path)?;
-        config.store()?;
+    let ctx = Context::new(cli.config, cli.profile, cli.workspace, cli.offline)?;
+
+    if let Ok(cfg) = ctx.config() {
+        cfg.settings.color.apply();
+        cfg.settings.prompt_answer.apply();
     }
 
     match &cli.command {
@@ -88,7 +227,7 @@ async fn main() -> anyhow::Result<()> {
         Some(Commands::Dbg) => {
             let start = std::time::Instant::now();
 
-            let api_client = ApiClient::new()?;
+            let api_client = ApiClient::new(&ctx)?;
 
             let tasks = api_client.get_tasks().await?;
 
@@ -111,29 +250,97 @@ async fn main() -> anyhow::Result<()> {
             username,
             course,
             host,
+            api_flavor,
+            credential_store,
+            credential_helper,
+            password_command,
         }) => {
-            configure(username, course, host).await?;
+            configure(
+                &ctx,
+                username.as_deref(),
+                course.as_deref(),
+                host.as_deref(),
+                *api_flavor,
+                *credential_store,
+                credential_helper.as_deref(),
+                password_command.as_deref(),
+            )
+            .await?;
         }
 
         Some(Commands::List) => {
             dbg!("list tasks");
-            list_tasks().await?;
+            list_tasks(&ctx).await?;
+        }
+
+        Some(Commands::Info { task }) => {
+            info(&ctx, task)?;
         }
 
-        Some(Commands::Login) => {
-            login().await?;
+        Some(Commands::Graph { format }) => {
+            graph(&ctx, *format)?;
         }
 
+        Some(Commands::Login { status: true, .. }) => {
+            login_status(&ctx).await?;
+        }
+
+        Some(Commands::Login {
+            token,
+            token_stdin,
+            password_stdin,
+            browser,
+            ..
+        }) => {
+            login(
+                &ctx,
+                token.as_deref(),
+                *token_stdin,
+                *password_stdin,
+                *browser,
+            )
+            .await?;
+        }
+
+        Some(Commands::Logout { all }) => {
+            logout(&ctx, *all).await?;
+        }
+
+        Some(Commands::Config { command }) => match command {
+            ConfigCommands::Get { key } => config_get(&ctx, key)?,
+            ConfigCommands::Set { key, value } => config_set(&ctx, key, value)?,
+            ConfigCommands::Unset { key } => config_unset(&ctx, key)?,
+            ConfigCommands::List { show_origin } => config_list(&ctx, *show_origin)?,
+        },
+
+        Some(Commands::Queue { command }) => match command {
+            QueueCommands::List => queue_list(&ctx)?,
+            QueueCommands::Flush => queue_flush(&ctx).await?,
+            QueueCommands::Drop { id, all } => queue_drop(&ctx, *id, *all)?,
+        },
+
+        Some(Commands::Profile { command }) => match command {
+            ProfileCommands::List => profile_list(&ctx)?,
+            ProfileCommands::Add {
+                name,
+                username,
+                course,
+                host,
+            } => profile_add(&ctx, name, username, course.as_deref(), host).await?,
+            ProfileCommands::Use { name } => profile_use(&ctx, name)?,
+            ProfileCommands::Remove { name } => profile_remove(&ctx, name)?,
+        },
+
         Some(Commands::Start { task_id }) => {
-            start_task(*task_id).await?;
+            start_task(&ctx, *task_id).await?;
         }
 
-        Some(Commands::Submit { path }) => {
-            submit_task(path).await?;
+        Some(Commands::Submit { path, task }) => {
+            submit_task(&ctx, path, task.as_deref()).await?;
         }
 
         Some(Commands::Sync { force, submissions }) => {
-            sync(*force, *submissions).await?;
+            sync(&ctx, *force, *submissions).await?;
         }
 
         _ => {}
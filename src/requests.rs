@@ -1,35 +1,356 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
 
-use reqwest::header::COOKIE;
-use reqwest::Client;
-use serde::Deserialize;
+use clap::ValueEnum;
+use colored::Colorize;
+use reqwest::header::{CONTENT_TYPE, COOKIE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
-    config::{self, Config},
-    tasks::models::{SubmissionGet, SubmissionPost, Task},
+    auth,
+    cache::ResponseCache,
+    config::{Config, Context},
+    credentials,
+    tasks::models::{Course, SubmissionGet, SubmissionPost, Task},
+    util::{format_age, is_interactive},
 };
 
+/// How long we wait for SmartBeans to accept a connection before working offline
+const CONNECT_TIMEOUT_SECONDS: u64 = 10;
+/// The cached response of [`ApiClient::get_solved_task_ids`]
+pub const PROGRESS_CACHE_KEY: &str = "progress";
+
+/// Which kind of SmartBeans server we're talking to.
+/// The local dev server's API differs from the production one in a few places,
+/// e.g. `simplified` and `timestamp` of submissions, see [`crate::tasks::models`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiFlavor {
+    Production,
+    Development,
+}
+
+impl ApiFlavor {
+    /// Tells the flavors apart by a submission, whose `simplified` and `timestamp` are strings only in the dev API.
+    /// Returns `None` if the submission doesn't tell.
+    pub fn detect(submission: &serde_json::Value) -> Option<Self> {
+        [&submission["simplified"], &submission["timestamp"]]
+            .into_iter()
+            .find_map(|value| match value {
+                serde_json::Value::String(_) => Some(ApiFlavor::Development),
+                serde_json::Value::Object(_) | serde_json::Value::Number(_) => {
+                    Some(ApiFlavor::Production)
+                }
+                _ => None,
+            })
+    }
+
+    /// Fallback until the flavor is detected: the dev server usually runs locally
+    pub fn guess(host: &str) -> Self {
+        let is_local = Url::parse(host)
+            .ok()
+            .and_then(|url| url.host_str().map(is_loopback))
+            .unwrap_or(false);
+
+        if is_local {
+            ApiFlavor::Development
+        } else {
+            ApiFlavor::Production
+        }
+    }
+}
+
+impl Display for ApiFlavor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiFlavor::Production => write!(f, "production"),
+            ApiFlavor::Development => write!(f, "development"),
+        }
+    }
+}
+
+/// What we found when probing a host
+pub enum HostProbe {
+    SmartBeans,
+    /// Something answered, but not a SmartBeans API. Holds a description of what we got instead.
+    Other(String),
+}
+
+/// Turns whatever the user typed or copied from the browser into the base URL the API lives under.
+/// Adds a missing scheme, and drops trailing slashes, query strings, fragments and the `/api` part.
+pub fn normalize_host(host: &str) -> anyhow::Result<String> {
+    let host = host.trim();
+    let host = if host.contains("://") {
+        host.to_string()
+    } else if is_loopback(host.split([':', '/']).next().unwrap_or_default()) {
+        // the dev server doesn't do TLS
+        format!("http://{}", host)
+    } else {
+        format!("https://{}", host)
+    };
+
+    let mut url =
+        Url::parse(&host).map_err(|e| anyhow::anyhow!("`{}` is not a valid URL: {}", host, e))?;
+    url.set_query(None);
+    url.set_fragment(None);
+
+    let path = url.path().trim_end_matches('/').to_string();
+    let path = match path.find("/api") {
+        Some(i) if path[i..] == *"/api" || path[i..].starts_with("/api/") => &path[..i],
+        _ => &path,
+    };
+    url.set_path(path);
+
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+fn is_loopback(host: &str) -> bool {
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]" | "::1")
+}
+
+/// GET /api/courses
+/// Checks that `host` serves a SmartBeans API. The endpoint may require a login,
+/// but even then SmartBeans answers in JSON, unlike a web server or the frontend at a wrong path.
+pub async fn probe_host(host: &str) -> anyhow::Result<HostProbe> {
+    let res = Client::new()
+        .get(format!("{}/api/courses", host))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?;
+
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.contains("json"))
+        .unwrap_or(false);
+
+    if !is_json {
+        return Ok(HostProbe::Other(format!(
+            "{}/api answered with status {} and no JSON",
+            host,
+            res.status()
+        )));
+    }
+
+    Ok(HostProbe::SmartBeans)
+}
+
+/// Returned when the server rejects our session and we can't log in again on our own
+#[derive(Debug)]
+pub struct SessionExpired;
+
+impl Display for SessionExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Your session has expired. Please run `{} login` to log in again.",
+            env!("CARGO_PKG_NAME")
+        )
+    }
+}
+
+impl std::error::Error for SessionExpired {}
+
 /// An API client for SmartBeans
+///
+/// Cloning is cheap and clones share the session token,
+/// so a re-login in one clone is picked up by all others.
+#[derive(Clone)]
 pub struct ApiClient {
     client: Client,
+    ctx: Context,
     config: Config,
+    flavor: ApiFlavor,
+    token: Arc<RwLock<String>>,
+    /// Serializes re-logins, so concurrent requests failing at once only prompt the user once
+    relogin: Arc<tokio::sync::Mutex<()>>,
+    cache: ResponseCache,
+    /// Set by `--offline`, or once SmartBeans turned out to be unreachable
+    offline: Arc<AtomicBool>,
+    /// The kinds of cached data whose age was already reported
+    reported_stale: Arc<Mutex<HashSet<&'static str>>>,
 }
 
 impl ApiClient {
-    pub fn new() -> anyhow::Result<Self> {
-        let client = Client::new();
-        let config = Config::load()?;
-        Ok(Self { client, config })
+    pub fn new(ctx: &Context) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECONDS))
+            .build()?;
+        let mut config = ctx.config()?;
+        let token = Arc::new(RwLock::new(credentials::load_token(&mut config)?));
+        let flavor = config
+            .api_flavor
+            .unwrap_or_else(|| ApiFlavor::guess(&config.host));
+        Ok(Self {
+            client,
+            ctx: ctx.clone(),
+            flavor,
+            token,
+            relogin: Arc::new(tokio::sync::Mutex::new(())),
+            cache: ctx.cache(&config.profile),
+            offline: Arc::new(AtomicBool::new(ctx.offline)),
+            reported_stale: Arc::new(Mutex::new(HashSet::new())),
+            config,
+        })
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::Relaxed)
+    }
+
+    /// Works with cached data from now on, for callers that tell the user about the unreachable host themselves
+    pub fn set_offline(&self) {
+        self.offline.store(true, Ordering::Relaxed);
+    }
+
+    fn go_offline(&self) {
+        if !self.offline.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "{}",
+                "SmartBeans can't be reached, working with cached data.".yellow()
+            );
+        }
+    }
+
+    /// Fetches `what` and caches it under `key`.
+    /// While offline, or once SmartBeans turns out to be unreachable, the cached copy is returned instead
+    /// and its age is reported, once for every kind of data.
+    async fn cached<T, F, Fut>(&self, what: &'static str, key: &str, fetch: F) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        if !self.is_offline() {
+            match fetch().await {
+                Ok(data) => {
+                    if let Err(e) = self.cache.store(key, &data) {
+                        eprintln!("Could not cache the {}: {}", what, e);
+                    }
+                    return Ok(data);
+                }
+                Err(e) if is_unreachable(&e) => self.go_offline(),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let cached = self.cache.load::<T>(key).ok_or_else(|| {
+            anyhow::anyhow!(
+                "You're offline and there is no cached copy of the {} yet. Run the command once while SmartBeans can be reached.",
+                what
+            )
+        })?;
+
+        if self.reported_stale.lock().unwrap().insert(what) {
+            eprintln!(
+                "{} using the {} cached {}",
+                "Offline:".yellow(),
+                what,
+                format_age(cached.fetched_at)
+            );
+        }
+
+        Ok(cached.data)
     }
 
+    #[allow(dead_code)]
     pub fn check_auth(&self) -> anyhow::Result<()> {
-        if self.config.token.is_empty() {
+        if self.token().is_empty() {
             anyhow::bail!("You are not authenticated. Please run `sb login` first.");
         }
         Ok(())
     }
 
-    // pub fn login()
+    fn token(&self) -> String {
+        self.token.read().unwrap().clone()
+    }
+
+    /// Sends the request produced by `build` with the session cookie attached.
+    /// If the server rejects the session, we log in again (or fail with [`SessionExpired`]
+    /// when there's no terminal to prompt on) and retry the request once.
+    async fn send<F>(&self, build: F) -> anyhow::Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let token = self.token();
+        let res = build(&self.client)
+            .header(COOKIE, format!("token={}", token))
+            .send()
+            .await?;
+
+        if !is_auth_failure(res.status()) {
+            return Ok(res);
+        }
+
+        self.relogin(&token).await?;
+
+        let res = build(&self.client)
+            .header(COOKIE, format!("token={}", self.token()))
+            .send()
+            .await?;
+
+        if is_auth_failure(res.status()) {
+            return Err(SessionExpired.into());
+        }
+
+        Ok(res)
+    }
+
+    /// Sends a request and deserializes the response body, failing on error statuses
+    /// instead of surfacing an opaque decode error
+    async fn send_json<T, F>(&self, build: F) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let res = self.send(build).await?;
+        let url = res.url().clone();
+        let status = res.status();
+        if !status.is_success() {
+            anyhow::bail!("Request to {} failed with status {}", url, status);
+        }
+
+        res.json().await.map_err(|e| {
+            anyhow::anyhow!(
+                "Unexpected response from {} ({} API): {}",
+                url,
+                self.flavor,
+                e
+            )
+        })
+    }
+
+    /// Logs in again, unless another request already did so since `rejected_token` was sent
+    async fn relogin(&self, rejected_token: &str) -> anyhow::Result<()> {
+        let _guard = self.relogin.lock().await;
+
+        if self.token() != rejected_token {
+            return Ok(());
+        }
+
+        let cfg = self.ctx.config()?;
+        if !is_interactive() && !auth::has_unattended_password(&cfg) {
+            return Err(SessionExpired.into());
+        }
+
+        println!("Your session has expired.");
+        auth::login(&self.ctx, false).await?;
+
+        let mut cfg = self.ctx.config()?;
+        *self.token.write().unwrap() = credentials::load_token(&mut cfg)?;
+
+        Ok(())
+    }
 
     /// GET /api/courses/{courseId}/progress
     /// Needs to be authenticated
@@ -40,14 +361,10 @@ impl ApiClient {
             self.config.host, self.config.course
         );
 
-        let client = reqwest::Client::new();
-        let res = client
-            .get(url)
-            .header(COOKIE, format!("token={}", self.config.token))
-            .send()
-            .await?;
-        let progress: Vec<usize> = res.json().await?;
-        Ok(progress)
+        self.cached("progress", PROGRESS_CACHE_KEY, || {
+            self.send_json(|client| client.get(&url))
+        })
+        .await
     }
 
     pub async fn get_tasks(&self) -> anyhow::Result<Vec<Task>> {
@@ -56,37 +373,23 @@ impl ApiClient {
             self.config.host, self.config.course
         );
 
-        let res = self
-            .client
-            .get(url)
-            .header(COOKIE, format!("token={}", self.config.token))
-            .send()
-            .await?;
-
-        let tasks: Vec<Task> = res.json().await?;
-        Ok(tasks)
+        self.cached("task list", "tasks", || {
+            self.send_json(|client| client.get(&url))
+        })
+        .await
     }
 
     pub async fn get_submission(
-        host: &str,
-        course: &str,
-        token: &str,
+        &self,
         task_id: usize,
         submission_id: usize,
-        client: &Client,
     ) -> anyhow::Result<serde_json::Value> {
         let url = format!(
             "{}/api/courses/{}/tasks/{}/submissions/{}",
-            host, course, task_id, submission_id
+            self.config.host, self.config.course, task_id, submission_id
         );
 
-        let res = client
-            .get(url)
-            .header(COOKIE, format!("token={}", token))
-            .send()
-            .await?;
-
-        Ok(res.json::<serde_json::Value>().await?)
+        self.send_json(|client| client.get(&url)).await
     }
 
     pub async fn get_submissions(&self, task_id: usize) -> anyhow::Result<Vec<SubmissionGet>> {
@@ -95,39 +398,35 @@ impl ApiClient {
             self.config.host, self.config.course, task_id
         );
 
-        let res = self
-            .client
-            .get(url)
-            .header(COOKIE, format!("token={}", self.config.token))
-            .send()
-            .await?;
-
-        Ok(res.json().await?)
+        self.send_json(|client| client.get(&url)).await
     }
 
     pub async fn get_detailed_submissions(
         &self,
         task_id: usize,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        self.cached("submissions", &format!("submissions-{}", task_id), || {
+            self.fetch_detailed_submissions(task_id)
+        })
+        .await
+    }
+
+    async fn fetch_detailed_submissions(
+        &self,
+        task_id: usize,
     ) -> anyhow::Result<Vec<serde_json::Value>> {
         use futures::future::join_all;
 
         let submissions = self.get_submissions(task_id).await?;
         let mut submission_futures = Vec::new();
 
-        let host = &self.config.host;
-        let course = &self.config.course;
-        let token = &self.config.token;
-        let client = &self.client;
         for submission in submissions {
-            let host = host.clone();
-            let course = course.clone();
-            let token = token.clone();
-            let client = client.clone();
-
-            let future = tokio::spawn(async move {
-                ApiClient::get_submission(&host, &course, &token, task_id, submission.id, &client)
-                    .await
-            });
+            let api_client = self.clone();
+
+            let future =
+                tokio::spawn(
+                    async move { api_client.get_submission(task_id, submission.id).await },
+                );
             submission_futures.push(future);
         }
 
@@ -142,30 +441,42 @@ impl ApiClient {
             }
         }
 
+        if self.config.api_flavor.is_none() {
+            if let Some(flavor) = detailed_submissions.iter().find_map(ApiFlavor::detect) {
+                if let Err(e) = self.remember_flavor(flavor) {
+                    eprintln!("Could not store the detected API flavor: {}", e);
+                }
+            }
+        }
+
         Ok(detailed_submissions)
     }
 
-    pub async fn submit_task(&self, path: &Path) -> anyhow::Result<SubmissionResponsePost> {
-        let meta = config::meta::Meta::load()?;
-
-        let task_id = meta
-            .get_task_id_from_workspace(path)
-            .expect("Task not found at expected path");
-        let submission_content = std::fs::read_to_string(path)?;
+    /// Stores a detected flavor, unless one was stored in the meantime
+    fn remember_flavor(&self, flavor: ApiFlavor) -> anyhow::Result<()> {
+        let mut cfg = self.ctx.config()?;
+        if cfg.api_flavor.is_none() {
+            cfg.api_flavor = Some(flavor);
+            cfg.store()?;
+        }
+        Ok(())
+    }
 
+    /// Submits the contents of a solution, read beforehand so it can be queued as is when offline
+    pub async fn submit_task(
+        &self,
+        task_id: usize,
+        submission_content: &str,
+    ) -> anyhow::Result<SubmissionResponsePost> {
         let url = format!(
             "{}/api/courses/{}/tasks/{}/submissions",
             self.config.host, self.config.course, task_id
         );
 
         let mut request_body = HashMap::new();
-        request_body.insert("submission", &submission_content);
+        request_body.insert("submission", submission_content);
         let res = self
-            .client
-            .post(url)
-            .json(&request_body)
-            .header(COOKIE, format!("token={}", self.config.token))
-            .send()
+            .send(|client| client.post(&url).json(&request_body))
             .await?;
 
         if res.status().is_success() {
@@ -176,10 +487,93 @@ impl ApiClient {
     }
 }
 
+/// GET /api/courses
+/// Lists the courses available to the user.
+/// This works before a course has been configured, which is why it's not part of `ApiClient`.
+pub async fn get_courses(host: &str, token: &str) -> anyhow::Result<Vec<Course>> {
+    let res = Client::new()
+        .get(format!("{}/api/courses", host))
+        .header(COOKIE, format!("token={}", token))
+        .send()
+        .await?;
+
+    let status = res.status();
+    if !status.is_success() {
+        anyhow::bail!("Listing courses failed with status {}", status);
+    }
+
+    Ok(res.json().await?)
+}
+
+/// Whether the request failed because SmartBeans can't be reached, rather than because it rejected it
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+/// SmartBeans answers with 401 for missing or expired tokens, and 403 for revoked ones
+fn is_auth_failure(status: StatusCode) -> bool {
+    status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SubmissionResponsePost {
     pub result: SubmissionPost,
     #[serde(skip)]
     #[serde(rename = "newUnlockedAssets")]
+    #[allow(dead_code)]
     pub new_unlocked_assets: Vec<String>, // don't know the structure of this object, if it's not just a list of strings
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_flavor() {
+        let production = serde_json::json!({
+            "timestamp": 1697000000000u64,
+            "simplified": { "compiler": { "stdout": "", "exitCode": 0 } },
+        });
+        let development = serde_json::json!({
+            "timestamp": "1697000000000",
+            "simplified": "{\"compiler\":{\"stdout\":\"\",\"exitCode\":0}}",
+        });
+
+        assert_eq!(ApiFlavor::detect(&production), Some(ApiFlavor::Production));
+        assert_eq!(
+            ApiFlavor::detect(&development),
+            Some(ApiFlavor::Development)
+        );
+        assert_eq!(ApiFlavor::detect(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        let cases = [
+            ("c109-223.cloud.gwdg.de", "https://c109-223.cloud.gwdg.de"),
+            (
+                "https://c109-223.cloud.gwdg.de/",
+                "https://c109-223.cloud.gwdg.de",
+            ),
+            (
+                "https://c109-223.cloud.gwdg.de/api/",
+                "https://c109-223.cloud.gwdg.de",
+            ),
+            (
+                "https://example.org/smartbeans/api/courses/ckurs/tasks",
+                "https://example.org/smartbeans",
+            ),
+            ("https://example.org/#/courses/ckurs", "https://example.org"),
+            ("localhost:8080", "http://localhost:8080"),
+            ("  http://127.0.0.1:8080/api  ", "http://127.0.0.1:8080"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(normalize_host(input).unwrap(), expected, "{}", input);
+        }
+    }
+}
@@ -1,33 +1,129 @@
 use std::path::{Path, PathBuf};
 
+use anyhow::Context as _;
+use futures::StreamExt;
 use tokio::fs;
 
 use convert_case::{Case, Casing};
 
 use crate::{
-    config::{meta::Meta, Config},
+    config::{
+        meta::{Meta, SyncSummary},
+        Config, Context, Locked,
+    },
     requests::ApiClient,
 };
 
-use super::models::Task;
+use super::{
+    marker::{TaskMarker, MARKER_FILE_NAME},
+    models::{SubmissionGet, Task},
+};
 
 /// Ensures that the configuration file exists
-pub fn init_filesystem() -> anyhow::Result<()> {
-    let _ = Config::load()?;
-    let _ = Meta::load()?;
+pub fn init_filesystem(ctx: &Context) -> anyhow::Result<()> {
+    let _ = ctx.config()?;
+    let _ = Meta::load(ctx)?;
 
     Ok(())
 }
 
-/// Creates the meta file if it doesn't exist
-/// and initializes it with the number of tasks for the course and the order
-pub fn init_meta(tasks: &[Task]) -> anyhow::Result<()> {
-    let meta = Meta::load()?;
-    if meta.total_tasks == 0 {
-        let meta = Meta::new(tasks);
-        meta.save()?;
+/// Merges the tasks from the server into the meta and moves the directories of renamed tasks along.
+/// Returns the updated meta with what changed.
+pub fn update_meta(ctx: &Context, tasks: &[Task]) -> anyhow::Result<(Meta, SyncSummary)> {
+    let mut meta = Meta::lock(ctx)?;
+    let mut summary = meta.merge_tasks(tasks)?;
+
+    for (task_id, from, to) in std::mem::take(&mut summary.moved) {
+        if from.exists() {
+            // never merge two directories, the task keeps its old one
+            if to.exists() {
+                meta.set_task_path(task_id, from.clone());
+                summary.not_moved.push((task_id, from, to));
+                continue;
+            }
+            std::fs::rename(&from, &to)?;
+        }
+        summary.moved.push((task_id, from, to));
+    }
+
+    Ok((meta.store()?, summary))
+}
+
+/// Moves the synced workspace, including the `submissions` folders, to a new directory and updates the meta.
+/// The target must not exist yet or be empty, so nothing gets overwritten.
+pub fn move_workspace(mut meta: Locked<Meta>, to: &Path) -> anyhow::Result<()> {
+    let from = meta.directory_dir().to_path_buf();
+
+    // copying a directory into itself would never end
+    let (canonical_from, canonical_to) =
+        (canonicalize_existing(&from)?, canonicalize_existing(to)?);
+    if canonical_to.starts_with(&canonical_from) || canonical_from.starts_with(&canonical_to) {
+        anyhow::bail!(
+            "Can't move the workspace from {} to {}, one is inside the other",
+            from.display(),
+            to.display()
+        );
+    }
+
+    if to.exists() && std::fs::read_dir(to)?.next().is_some() {
+        anyhow::bail!(
+            "Can't move the workspace to {}, the directory is not empty",
+            to.display()
+        );
+    }
+
+    if from.exists() {
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if to.exists() {
+            std::fs::remove_dir(to)?;
+        }
+
+        // renaming fails across file systems, in that case we copy and delete
+        if std::fs::rename(&from, to).is_err() {
+            copy_dir_all(&from, to)?;
+            std::fs::remove_dir_all(&from)?;
+        }
+    }
+
+    meta.relocate(to.to_path_buf());
+    meta.store()?;
+    Ok(())
+}
+
+/// Resolves symlinks and `..` in the part of `path` that exists, so paths that don't exist yet can be compared too
+fn canonicalize_existing(path: &Path) -> anyhow::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return Ok(path.clone()),
+        }
     }
 
+    let mut canonical = existing.canonicalize()?;
+    canonical.extend(rest.into_iter().rev());
+    Ok(canonical)
+}
+
+fn copy_dir_all(from: &Path, to: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
     Ok(())
 }
 
@@ -39,55 +135,87 @@ pub fn make_task_path(task: &Task, task_dir: &Path) -> anyhow::Result<(PathBuf,
         format!("{:04}_{}", task.order_by, task.task_description.shortname).to_case(Case::Snake),
     );
 
-    let task_path = dir_path.join(format!("{}.{}", task.taskid, task.lang));
+    let task_path = dir_path.join(task_file_name(task));
 
     Ok((dir_path, task_path))
 }
 
+/// e.g. `42.c`
+fn task_file_name(task: &Task) -> String {
+    format!("{}.{}", task.taskid, task.lang)
+}
+
 /// Replicates the directory structure of the exercises on the server
-/// in the exercises directory
+/// in the exercises directory.
+/// Returns what changed since the last sync.
 pub async fn sync_tasks_async(
+    ctx: &Context,
     force: bool,
     submissions: bool,
     client: &ApiClient,
-) -> anyhow::Result<()> {
-    init_filesystem()?;
+) -> anyhow::Result<SyncSummary> {
+    init_filesystem(ctx)?;
     let tasks = client.get_tasks().await?;
+    // before writing any files, so renamed tasks keep their code
+    let (meta, mut summary) = update_meta(ctx, &tasks)?;
+    let task_dir = meta.directory_dir().to_path_buf();
+    let cfg = ctx.config()?;
 
     let total_tasks = tasks.len();
     println!("Syncing {} tasks", total_tasks);
     let mut task_futures = Vec::new();
     for task in tasks.iter() {
         let future = async {
-            create_task_directories(task).await?;
-            if submissions {
-                // NOTE: probably no big benefit if we were to use a separate futures queue for this
-                sync_submissions_async(task, client).await?;
-            }
-            write_task_files(task, force).await?;
+            let result = async {
+                // the meta decides, a renamed task may have kept its old directory
+                let dir_path = match meta.get_task_path(task.taskid) {
+                    Some(path) => path.clone(),
+                    None => make_task_path(task, &task_dir)?.0,
+                };
+                create_task_directories(&dir_path).await?;
+                write_marker(task, &dir_path, &cfg).await?;
+                let updated = summary.updated.contains(&task.taskid);
+                write_task_files(task, &dir_path, force, updated).await?;
+                // last, so the task can be worked on even if its submissions can't be downloaded
+                if submissions {
+                    // NOTE: probably no big benefit if we were to use a separate futures queue for this
+                    sync_submissions_async(task, &dir_path, client)
+                        .await
+                        .context("Could not download the submissions")?;
+                }
 
-            anyhow::Ok(())
+                anyhow::Ok(())
+            };
+            (task.taskid, result.await)
         };
         task_futures.push(future);
     }
 
-    let _ = futures::future::join_all(task_futures).await;
+    let concurrency = ctx.config()?.settings.sync_concurrency.max(1);
+    let results = futures::stream::iter(task_futures)
+        .buffer_unordered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
 
-    // HACK positional stuff. make this more robust
-    // TODO
-    // Honestly, the whole persistence aspect of `Meta` should be reworked anyway
-    init_meta(&tasks)?;
-    Meta::update_progress(client).await?;
+    summary.failed = results
+        .into_iter()
+        .filter_map(|(task_id, result)| Some((task_id, format!("{:#}", result.err()?))))
+        .collect();
+    summary.failed.sort();
 
-    Ok(())
+    Meta::update_progress(ctx, client).await?;
+
+    Ok(summary)
 }
 
 /// Syncs the submissions for a task
-/// TODO using serde_json::Value is a bit of a hack I guess
-async fn sync_submissions_async(task: &Task, api_client: &ApiClient) -> anyhow::Result<()> {
+/// The raw response is kept as metadata, so nothing the server sends gets lost
+async fn sync_submissions_async(
+    task: &Task,
+    dir_path: &Path,
+    api_client: &ApiClient,
+) -> anyhow::Result<()> {
     let submissions = api_client.get_detailed_submissions(task.taskid).await?;
-    let meta = Meta::load()?;
-    let (dir_path, _) = make_task_path(task, meta.directory_dir())?;
     let submissions_dir = dir_path.join("submissions");
 
     if !submissions_dir.exists() {
@@ -96,36 +224,54 @@ async fn sync_submissions_async(task: &Task, api_client: &ApiClient) -> anyhow::
 
     // TODO make concurrent
     for submission in submissions {
-        let timestamp = submission.get("timestamp").unwrap().as_str().unwrap(); // sometimes int, sometimes string. String always deserializes correctly
-        let result_type = submission.get("resultType").unwrap().as_str().unwrap();
-        let path = submissions_dir.join(format!("{}-{}.{}", timestamp, result_type, task.lang));
+        let parsed: SubmissionGet = serde_json::from_value(submission.clone())?;
+        let path = submissions_dir.join(format!(
+            "{}-{}.{}",
+            parsed.timestamp, parsed.result_type, task.lang
+        ));
         let metadata_path = submissions_dir.join(format!(
             "{}-{}.{}.metadata.json",
-            timestamp, result_type, task.lang
+            parsed.timestamp, parsed.result_type, task.lang
         ));
 
         if !path.exists() {
-            fs::write(path, &submission.get("content").unwrap().to_string()).await?;
+            fs::write(path, &parsed.content).await?;
             fs::write(metadata_path, serde_json::to_string_pretty(&submission)?).await?;
         }
     }
     Ok(())
 }
 
-async fn create_task_directories(task: &Task) -> anyhow::Result<()> {
-    let meta = Meta::load()?;
-    let (dir_path, _) = make_task_path(task, meta.directory_dir())?;
+async fn create_task_directories(dir_path: &Path) -> anyhow::Result<()> {
     if !dir_path.exists() {
         fs::create_dir_all(dir_path).await?;
     }
     Ok(())
 }
 
-async fn write_task_files(task: &Task, force: bool) -> anyhow::Result<()> {
-    let meta = Meta::load()?;
-    let (dir_path, task_path) = make_task_path(task, meta.directory_dir())?;
+/// Written on every sync, so the marker follows changes to the profile
+async fn write_marker(task: &Task, dir_path: &Path, cfg: &Config) -> anyhow::Result<()> {
+    let marker = TaskMarker::new(task, cfg);
+    fs::write(dir_path.join(MARKER_FILE_NAME), marker.to_json()?).await?;
+    Ok(())
+}
+
+/// Writes the task file and README of new tasks.
+/// The README of an `updated` task is rewritten, its task file is left alone since it's the user's code.
+async fn write_task_files(
+    task: &Task,
+    dir_path: &Path,
+    force: bool,
+    updated: bool,
+) -> anyhow::Result<()> {
+    let task_path = dir_path.join(task_file_name(task));
     let readme_file_path = dir_path.join("README.md");
 
+    if updated && task_path.exists() && !force {
+        fs::write(readme_file_path, &task.task_description.task).await?;
+        return Ok(());
+    }
+
     if force || !task_path.exists() {
         let content = if task.task_description.default_editor_input.is_empty() {
             "// Write your code here, and submit your solution once you're done!\n// Read the README for instructions\n"
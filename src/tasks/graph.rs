@@ -0,0 +1,254 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::config::meta::Meta;
+
+use super::models::Task;
+
+/// Group of tasks without tags
+const UNTAGGED: &str = "untagged";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, e.g. `sbcli graph | dot -Tsvg > course.svg`
+    #[default]
+    Dot,
+    /// Mermaid flowchart, renders in GitHub and GitLab markdown
+    Mermaid,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Solved,
+    /// All prerequisites are solved
+    Unlocked,
+    Locked,
+}
+
+impl TaskState {
+    fn color(self) -> &'static str {
+        match self {
+            TaskState::Solved => "#a3d9a5",
+            TaskState::Unlocked => "#ffe08a",
+            TaskState::Locked => "#d9d9d9",
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            TaskState::Solved => "solved",
+            TaskState::Unlocked => "unlocked",
+            TaskState::Locked => "locked",
+        }
+    }
+}
+
+struct Node<'a> {
+    task: &'a Task,
+    state: TaskState,
+}
+
+/// The tasks of a course with edges from each prerequisite to the tasks that need it.
+/// Tasks are grouped by their first tag, archived tasks are left out.
+pub struct TaskGraph<'a> {
+    /// <group, nodes>, in the order of the tasks
+    groups: Vec<(&'a str, Vec<Node<'a>>)>,
+    /// <prerequisite_id, task_id>
+    edges: Vec<(usize, usize)>,
+}
+
+impl<'a> TaskGraph<'a> {
+    pub fn new(meta: &'a Meta) -> Self {
+        let mut groups: Vec<(&str, Vec<Node>)> = Vec::new();
+        let mut edges = Vec::new();
+
+        let mut tasks = meta
+            .tasks()
+            .iter()
+            .filter(|task| !meta.is_archived(task.taskid))
+            .collect::<Vec<_>>();
+        tasks.sort_by_key(|task| task.order_by);
+
+        for task in tasks {
+            let state = if meta.solved_task_ids().contains(&task.taskid) {
+                TaskState::Solved
+            } else if meta.missing_prerequisites(task.taskid).is_empty() {
+                TaskState::Unlocked
+            } else {
+                TaskState::Locked
+            };
+
+            let group = task.tags.first().map_or(UNTAGGED, |tag| tag.name.as_str());
+            let node = Node { task, state };
+            match groups.iter_mut().find(|(name, _)| *name == group) {
+                Some((_, nodes)) => nodes.push(node),
+                None => groups.push((group, vec![node])),
+            }
+
+            for prerequisite in meta.prerequisites(task.taskid) {
+                edges.push((prerequisite.taskid, task.taskid));
+            }
+        }
+
+        Self { groups, edges }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> anyhow::Result<String> {
+        Ok(match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+            GraphFormat::Json => serde_json::to_string_pretty(&self.to_json())?,
+        })
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph course {\n");
+        out.push_str("    rankdir=LR;\n");
+        out.push_str("    node [shape=box, style=\"rounded,filled\"];\n");
+
+        for (index, (group, nodes)) in self.groups.iter().enumerate() {
+            out.push_str(&format!("    subgraph cluster_{} {{\n", index));
+            out.push_str(&format!("        label=\"{}\";\n", escape_dot(group)));
+            for node in nodes {
+                out.push_str(&format!(
+                    "        t{} [label=\"{}\", fillcolor=\"{}\"];\n",
+                    node.task.taskid,
+                    escape_dot(&label(node.task)),
+                    node.state.color()
+                ));
+            }
+            out.push_str("    }\n");
+        }
+
+        for (from, to) in &self.edges {
+            out.push_str(&format!("    t{} -> t{};\n", from, to));
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart LR\n");
+
+        for (index, (group, nodes)) in self.groups.iter().enumerate() {
+            out.push_str(&format!(
+                "    subgraph g{} [\"{}\"]\n",
+                index,
+                escape_mermaid(group)
+            ));
+            for node in nodes {
+                out.push_str(&format!(
+                    "        t{}[\"{}\"]:::{}\n",
+                    node.task.taskid,
+                    escape_mermaid(&label(node.task)),
+                    node.state.class()
+                ));
+            }
+            out.push_str("    end\n");
+        }
+
+        for (from, to) in &self.edges {
+            out.push_str(&format!("    t{} --> t{}\n", from, to));
+        }
+
+        for state in [TaskState::Solved, TaskState::Unlocked, TaskState::Locked] {
+            out.push_str(&format!(
+                "    classDef {} fill:{}\n",
+                state.class(),
+                state.color()
+            ));
+        }
+        out
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let nodes = self
+            .groups
+            .iter()
+            .flat_map(|(group, nodes)| {
+                nodes.iter().map(move |node| {
+                    serde_json::json!({
+                        "taskid": node.task.taskid,
+                        "shortname": node.task.task_description.shortname,
+                        "title": node.task.task_description.title,
+                        "orderBy": node.task.order_by,
+                        "group": group,
+                        "state": node.state,
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let edges = self
+            .edges
+            .iter()
+            .map(|(from, to)| serde_json::json!({ "from": from, "to": to }))
+            .collect::<Vec<_>>();
+
+        serde_json::json!({ "nodes": nodes, "edges": edges })
+    }
+}
+
+fn label(task: &Task) -> String {
+    let description = &task.task_description;
+    if description.title.is_empty() || description.title == description.shortname {
+        description.shortname.clone()
+    } else {
+        format!("{}\n{}", description.shortname, description.title)
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_mermaid(text: &str) -> String {
+    text.replace('"', "#quot;").replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tasks::models::Tag;
+
+    #[test]
+    fn test_render_graph() {
+        let task = |taskid, shortname, tag: &str, prerequisites| Task {
+            tags: vec![Tag {
+                name: tag.to_string(),
+                points: None,
+            }],
+            ..Task::fixture(taskid, shortname, prerequisites)
+        };
+        let mut meta = Meta::new(
+            &[
+                task(1, "hello", "basics", &[]),
+                task(2, "loops", "basics", &["hello"]),
+                task(3, "pointers", "advanced", &["loops"]),
+            ],
+            PathBuf::from("tasks"),
+        );
+        meta.set_solved_tasks_ids(vec![1]);
+        let graph = TaskGraph::new(&meta);
+
+        let dot = graph.render(GraphFormat::Dot).unwrap();
+        assert!(dot.contains("label=\"advanced\""), "{}", dot);
+        assert!(dot.contains("t1 [label=\"hello\", fillcolor=\"#a3d9a5\"]"));
+        assert!(dot.contains("t2 -> t3;"));
+
+        let mermaid = graph.render(GraphFormat::Mermaid).unwrap();
+        assert!(mermaid.contains("t2[\"loops\"]:::unlocked"), "{}", mermaid);
+        assert!(mermaid.contains("t3[\"pointers\"]:::locked"));
+        assert!(mermaid.contains("t1 --> t2"));
+
+        let json = graph.to_json();
+        assert_eq!(json["nodes"][2]["state"], "locked");
+        assert_eq!(json["edges"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+use super::models::Task;
+
+/// Name of the marker file in every synced task directory
+pub const MARKER_FILE_NAME: &str = ".sbcli-task.json";
+
+/// Identifies the task a directory belongs to, so it can be found after the directory was moved or renamed
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskMarker {
+    pub task_id: usize,
+    pub course: String,
+    pub host: String,
+    pub lang: String,
+}
+
+impl TaskMarker {
+    pub fn new(task: &Task, cfg: &Config) -> Self {
+        Self {
+            task_id: task.taskid,
+            course: cfg.course.clone(),
+            host: cfg.host.clone(),
+            lang: task.lang.clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Looks for a marker in `path` and its parents, returning the task directory and its marker.
+    /// Broken markers are skipped rather than failing, a parent may still have a valid one.
+    pub fn find(path: &Path) -> Option<(PathBuf, Self)> {
+        path.ancestors().find_map(|dir| {
+            let contents = std::fs::read_to_string(dir.join(MARKER_FILE_NAME)).ok()?;
+            let marker = serde_json::from_str(&contents).ok()?;
+            Some((dir.to_path_buf(), marker))
+        })
+    }
+
+    /// Whether the task belongs to the account in `cfg`
+    pub fn matches(&self, cfg: &Config) -> bool {
+        self.host == cfg.host && self.course == cfg.course
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let nested = dir.join("moved").join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        let marker = TaskMarker {
+            task_id: 3,
+            course: "ckurs".to_string(),
+            host: "https://sb.example".to_string(),
+            lang: "c".to_string(),
+        };
+        std::fs::write(
+            dir.join("moved").join(MARKER_FILE_NAME),
+            marker.to_json().unwrap(),
+        )
+        .unwrap();
+
+        let (task_dir, found) = TaskMarker::find(&nested.join("3.c")).unwrap();
+        assert_eq!(task_dir, dir.join("moved"));
+        assert_eq!(found, marker);
+
+        assert!(TaskMarker::find(Path::new("/")).is_none());
+    }
+}
@@ -1,3 +1,5 @@
 pub mod files;
+pub mod graph;
+pub mod marker;
 pub mod models;
 pub mod open;
@@ -1,10 +1,46 @@
 use std::{collections::HashMap, fmt::Display};
 
-use serde::{Deserialize, Serialize};
+use chrono::DateTime;
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 
+/// A course as listed by the server.
+/// Depending on the SmartBeans version, courses come as plain names or as objects.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Course {
+    Name(String),
+    Detailed {
+        #[serde(alias = "id", alias = "shortname")]
+        name: String,
+        #[serde(default)]
+        title: Option<String>,
+    },
+}
+
+impl Course {
+    pub fn name(&self) -> &str {
+        match self {
+            Course::Name(name) => name,
+            Course::Detailed { name, .. } => name,
+        }
+    }
+}
+
+impl Display for Course {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Course::Detailed {
+                name,
+                title: Some(title),
+            } => write!(f, "{} ({})", name, title),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
 /// Represents an exercise
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Task {
     pub taskid: usize,
     #[serde(rename = "taskDescription")]
@@ -18,7 +54,23 @@ pub struct Task {
     pub unlockable_assets: Option<Vec<String>>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg(test)]
+impl Task {
+    /// A C task ordered by its id, change the rest with `Task { .., ..Task::fixture(..) }`
+    pub fn fixture(taskid: usize, shortname: &str, prerequisites: &[&str]) -> Self {
+        let mut task = Task {
+            taskid,
+            order_by: taskid,
+            lang: "c".to_string(),
+            prerequisites: prerequisites.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        };
+        task.task_description.shortname = shortname.to_string();
+        task
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct TaskDescription {
     pub task: String,
     pub title: String,
@@ -27,7 +79,7 @@ pub struct TaskDescription {
     pub default_editor_input: String,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Tag {
     pub name: String,
     pub points: Option<usize>,
@@ -42,6 +94,7 @@ pub struct SubmissionPost {
     pub content: String,
     #[serde(rename = "resultType")]
     pub result_type: String,
+    #[serde(deserialize_with = "Simplified::deserialize_compat")]
     pub simplified: Simplified,
     pub details: HashMap<String, Value>,
     pub score: f32,
@@ -60,12 +113,13 @@ pub struct SubmissionGet {
     pub id: usize,
     pub course: String,
     pub taskid: usize,
-    #[serde(skip)]
-    pub timestamp: usize, // this is somehow either a string or a number, depending on javascripts whim. Skippping it for now.
+    #[serde(default)]
+    pub timestamp: Timestamp,
     pub content: String,
     #[serde(rename = "resultType")]
     pub result_type: String,
-    pub simplified: Simplified, // this is a string in the dev api, but an object in the prod api.
+    #[serde(deserialize_with = "Simplified::deserialize_compat")]
+    pub simplified: Simplified,
     pub details: HashMap<String, Value>,
     pub score: f32,
 }
@@ -85,20 +139,138 @@ pub struct SubmissionGet {
 // }
 // }
 
+/// When a submission was made, in milliseconds since the epoch.
+///
+/// The production API sends a number, the dev API a string, which holds either the number or an RFC 3339 date.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Timestamp(pub u64);
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(millis) => Ok(Timestamp(millis)),
+            Raw::Text(text) => text
+                .parse::<u64>()
+                .ok()
+                .or_else(|| {
+                    let date = DateTime::parse_from_rfc3339(&text).ok()?;
+                    u64::try_from(date.timestamp_millis()).ok()
+                })
+                .map(Timestamp)
+                .ok_or_else(|| D::Error::custom(format!("invalid timestamp `{}`", text))),
+        }
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Simplified {
     pub compiler: Compiler,
+    #[serde(rename = "testCase")]
+    pub test_case: Option<TestCase>,
+}
+
+impl Simplified {
+    /// The production API sends `simplified` as an object, the dev API as a string containing its JSON
+    fn deserialize_compat<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Object(Simplified),
+            Encoded(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Object(simplified) => Ok(simplified),
+            Raw::Encoded(text) if text.trim().is_empty() => Ok(Simplified::default()),
+            Raw::Encoded(text) => serde_json::from_str(&text).map_err(D::Error::custom),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Compiler {
     pub stdout: String,
     #[serde(rename = "exitCode")]
     pub exit_code: isize,
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TestCase {
+    pub message: Option<String>,
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    #[serde(rename = "expectedStdout")]
+    pub expected_stdout: Option<String>,
+    #[serde(rename = "exitCode")]
+    pub exit_code: Option<isize>,
+}
+
 impl Display for Compiler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.stdout)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_submission_from_both_apis() {
+        let prod = serde_json::json!({
+            "id": 1,
+            "course": "ckurs",
+            "taskid": 2,
+            "timestamp": 1681999999000u64,
+            "content": "int main() {}",
+            "resultType": "WRONG_ANSWER",
+            "simplified": { "compiler": { "stdout": "", "exitCode": 0 }, "testCase": { "stdout": "a", "expectedStdout": "b" } },
+            "details": {},
+            "score": 0,
+        });
+        let dev = serde_json::json!({
+            "id": 1,
+            "course": "ckurs",
+            "taskid": 2,
+            "timestamp": "1681999999000",
+            "content": "int main() {}",
+            "resultType": "WRONG_ANSWER",
+            "simplified": r#"{ "compiler": { "stdout": "", "exitCode": 0 }, "testCase": { "stdout": "a", "expectedStdout": "b" } }"#,
+            "details": {},
+            "score": 0,
+        });
+
+        for value in [prod, dev] {
+            let submission: SubmissionGet = serde_json::from_value(value).unwrap();
+            assert_eq!(submission.timestamp, Timestamp(1681999999000));
+            assert_eq!(
+                submission
+                    .simplified
+                    .test_case
+                    .unwrap()
+                    .expected_stdout
+                    .as_deref(),
+                Some("b")
+            );
+        }
+
+        let dated: Timestamp = serde_json::from_str(r#""2023-04-20T14:13:19Z""#).unwrap();
+        assert_eq!(dated, Timestamp(1681999999000));
+    }
+}
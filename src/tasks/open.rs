@@ -1,7 +1,18 @@
 use std::path::Path;
 
-pub fn open_task_in_editor(task_path: &Path) -> anyhow::Result<()> {
-    if open::that(task_path).is_err() {
+use crate::util::shell_command;
+
+/// Opens the task with the configured editor command, or the system default if there is none
+pub fn open_task_in_editor(task_path: &Path, editor: Option<&str>) -> anyhow::Result<()> {
+    let opened = match editor {
+        Some(editor) => shell_command(&format!("{} \"{}\"", editor, task_path.display()))
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false),
+        None => open::that(task_path).is_ok(),
+    };
+
+    if !opened {
         println!(
             "Could not open task file: {}.\nPlease open it manually in your preferred editor.",
             task_path.display()
@@ -10,5 +21,3 @@ pub fn open_task_in_editor(task_path: &Path) -> anyhow::Result<()> {
 
     Ok(())
 }
-
-
@@ -1,12 +1,174 @@
-use std::io::{self, Write};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-// TOOD move to utils
+use chrono::{DateTime, Utc};
+
+use crate::config::settings::PromptAnswer;
+
+/// Keeps temporary files of concurrent writes within this process apart
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Asks a yes/no question, unless the `prompt_answer` setting answers it for us
 pub fn prompt_for_consent(message: &str) -> bool {
+    ask_yes_no(message, true)
+}
+
+/// Like [`prompt_for_consent`], but only an explicit yes agrees. For questions whose answer is hard to undo.
+pub fn prompt_for_consent_default_no(message: &str) -> bool {
+    ask_yes_no(message, false)
+}
+
+fn ask_yes_no(message: &str, default: bool) -> bool {
+    let options = if default { "[Y/n]" } else { "[y/N]" };
+    match PromptAnswer::current() {
+        PromptAnswer::Yes => {
+            println!("{} {}: y", message, options);
+            return true;
+        }
+        PromptAnswer::No => {
+            println!("{} {}: n", message, options);
+            return false;
+        }
+        PromptAnswer::Ask => {}
+    }
+
+    let mut input = String::new();
+    print!("{} {}: ", message, options);
+    io::stdout().flush().unwrap();
+    // a closed stdin is no answer, so it never agrees
+    if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+        println!();
+        return false;
+    }
+
+    match input.trim().to_lowercase().as_str() {
+        "" => default,
+        input => input == "y" || input == "yes",
+    }
+}
+
+/// Asks for a line of input, returning `default` if the user just hits enter.
+/// Fails once stdin is closed, so callers that ask again on empty input don't loop forever.
+pub fn prompt(message: &str, default: Option<&str>) -> anyhow::Result<String> {
+    match default.filter(|d| !d.is_empty()) {
+        Some(default) => print!("{} [{}]: ", message, default),
+        None => print!("{}: ", message),
+    }
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        println!();
+        anyhow::bail!("No input, stdin was closed");
+    }
+
+    Ok(match input.trim() {
+        "" => default.unwrap_or_default().to_string(),
+        input => input.to_string(),
+    })
+}
+
+/// Whether we can prompt the user, i.e. stdin is attached to a terminal
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Builds a command that runs `command` through the platform's shell
+pub fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+/// Lets the user pick one of `options` by number. Returns `None` if the input is empty or invalid.
+pub fn pick<T: Display>(message: &str, options: &[T]) -> Option<usize> {
+    println!("{}", message);
+    for (i, option) in options.iter().enumerate() {
+        println!("{:>3}) {}", i + 1, option);
+    }
+
     let mut input = String::new();
-    print!("{} [Y/n]: ", message);
+    print!("Enter a number: ");
     io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut input).unwrap();
+    io::stdin().read_line(&mut input).ok()?;
+
+    match input.trim().parse::<usize>() {
+        Ok(n) if (1..=options.len()).contains(&n) => Some(n - 1),
+        _ => None,
+    }
+}
+
+/// Finds the candidate closest to `input`, if any is close enough to be a likely typo
+pub fn did_you_mean<'a>(
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (candidate, strsim::jaro_winkler(input, candidate)))
+        .filter(|(_, similarity)| *similarity > 0.8)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(candidate, _)| candidate)
+}
+
+/// Writes a file atomically: readers see either the old or the new contents, never a partial write
+pub fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // in the same directory, renaming across file systems isn't atomic
+    let tmp_path = sibling_path(
+        path,
+        &format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(contents)?;
+    tmp.sync_all()?;
+
+    if let Err(e) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// e.g. `config.ron` -> `config.ron.lock`
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// How long ago `time` was, e.g. "3 hours ago"
+pub fn format_age(time: DateTime<Utc>) -> String {
+    let age = Utc::now() - time;
+    let (amount, unit) = if age.num_days() > 0 {
+        (age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        (age.num_hours(), "hour")
+    } else if age.num_minutes() > 0 {
+        (age.num_minutes(), "minute")
+    } else {
+        return "just now".to_string();
+    };
 
-    let input = input.trim().to_lowercase();
-    input == "y" || input.is_empty()
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{} {}{} ago", amount, unit, plural)
 }